@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{self, Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{part::PART_EXTENSION, Error, Result};
+
+/// Name of the archive file created in the download folder by default.
+pub const ARCHIVE_FILE_NAME: &str = ".getbooru-archive";
+
+/// Append-only record of downloaded posts.
+///
+/// Each line holds a post id, the md5 of its file (`-` if unknown), the site
+/// the post comes from and the path the file was saved to, relative to the
+/// folder of the archive when within it. Sites tell apart posts sharing an
+/// id; lines of earlier versions, without a site, belong to the site the
+/// archive is opened for.
+///
+/// The file is read once on first use; new entries are appended as soon as
+/// a download completes, so an interrupted run never loses them.
+#[derive(Debug)]
+pub struct Archive {
+    path: PathBuf,
+    site: String,
+    entries: Mutex<Option<HashMap<String, Entry>>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Entry {
+    md5: Option<String>,
+    path: Option<PathBuf>,
+}
+
+impl Archive {
+    /// Open the archive at `path` for the posts of `site`, e.g.
+    /// `gelbooru.com`.
+    pub fn new(path: impl Into<PathBuf>, site: &str) -> Self {
+        Self {
+            path: path.into(),
+            site: site.to_owned(),
+            entries: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check whether the post with given id has been downloaded.
+    pub fn contains(&self, id: &str) -> Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        Ok(self.load(&mut entries)?.contains_key(id))
+    }
+
+    /// Get the recorded md5 of the post with given id, if any.
+    pub fn md5(&self, id: &str) -> Result<Option<String>> {
+        let mut entries = self.entries.lock().unwrap();
+        Ok(self
+            .load(&mut entries)?
+            .get(id)
+            .and_then(|entry| entry.md5.clone()))
+    }
+
+    /// Get the ids of the recorded posts whose file is still where it was
+    /// saved, within `folder`, along with the path of the file.
+    ///
+    /// Posts recorded without a path are left out.
+    pub fn saved_in(&self, folder: &Path) -> Result<Vec<(String, PathBuf)>> {
+        let folder = path::absolute(folder).map_err(|e| Error::io(folder, e))?;
+        let base = self.folder()?;
+        let mut entries = self.entries.lock().unwrap();
+
+        Ok(self
+            .load(&mut entries)?
+            .iter()
+            .filter_map(|(id, entry)| {
+                let path = base.join(entry.path.as_ref()?);
+                (path.starts_with(&folder) && path.is_file()).then(|| (id.clone(), path))
+            })
+            .collect())
+    }
+
+    /// Record a downloaded post, saved at `path` if known.
+    pub fn insert(&self, id: &str, md5: Option<&str>, path: Option<&Path>) -> Result<()> {
+        let path = path.map(|p| self.relative(p)).transpose()?;
+        let mut entries = self.entries.lock().unwrap();
+        let map = self.load(&mut entries)?;
+        let old = map.get(id);
+        if old.is_some_and(|old| {
+            (old.md5.is_some() || md5.is_none()) && (old.path.is_some() || path.is_none())
+        }) {
+            return Ok(());
+        }
+
+        let old = old.cloned().unwrap_or_default();
+        let entry = Entry {
+            md5: md5.map(str::to_owned).or(old.md5),
+            path: path.or(old.path),
+        };
+        self.append(&[(id, &entry)])?;
+        map.insert(id.to_owned(), entry);
+
+        Ok(())
+    }
+
+    /// Absolute path of the folder of the archive, which recorded paths are
+    /// relative to.
+    fn folder(&self) -> Result<PathBuf> {
+        let folder = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+
+        path::absolute(folder).map_err(|e| Error::io(folder, e))
+    }
+
+    /// Path of the file at `path` as recorded, relative to the folder of the
+    /// archive when within it.
+    fn relative(&self, path: &Path) -> Result<PathBuf> {
+        let path = path::absolute(path).map_err(|e| Error::io(path, e))?;
+
+        Ok(match path.strip_prefix(self.folder()?) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => path,
+        })
+    }
+
+    fn load<'a>(
+        &self,
+        entries: &'a mut Option<HashMap<String, Entry>>,
+    ) -> Result<&'a mut HashMap<String, Entry>> {
+        if entries.is_none() {
+            *entries = Some(match fs::read_to_string(&self.path) {
+                Ok(buf) => self.parse(&buf),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => self.seed()?,
+                Err(e) => return Err(Error::io(&self.path, e)),
+            });
+        }
+
+        Ok(entries.as_mut().unwrap())
+    }
+
+    /// Read the entries of the site of the archive.
+    fn parse(&self, buf: &str) -> HashMap<String, Entry> {
+        let mut map: HashMap<String, Entry> = HashMap::new();
+        for line in buf.lines() {
+            // The path comes last, as it may hold spaces.
+            let mut fields = line.splitn(4, ' ');
+            let id = fields.next().unwrap_or_default();
+            if id.is_empty() {
+                continue;
+            }
+            let md5 = fields.next().filter(|m| *m != "-").map(str::to_owned);
+            if fields.next().is_some_and(|site| site != self.site) {
+                continue;
+            }
+            let path = fields.next().filter(|p| !p.is_empty()).map(PathBuf::from);
+
+            // Later lines may add the md5 or the path of a post recorded
+            // without them.
+            let entry = map.entry(id.to_owned()).or_default();
+            if md5.is_some() {
+                entry.md5 = md5;
+            }
+            if path.is_some() {
+                entry.path = path;
+            }
+        }
+
+        map
+    }
+
+    /// Build a new archive out of the `id title.ext` files already present
+    /// next to it, so folders from earlier versions are not downloaded again.
+    /// Unfinished `.part` downloads are left out.
+    fn seed(&self) -> Result<HashMap<String, Entry>> {
+        let folder = self.folder()?;
+        let dir = match fs::read_dir(&folder) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(Error::io(&folder, e)),
+        };
+
+        let mut map = HashMap::new();
+        for file in dir {
+            let name = file.map_err(|e| Error::io(&folder, e))?.file_name();
+            let name = name.to_string_lossy();
+            if name
+                .rsplit('.')
+                .take(2)
+                .any(|extension| extension == PART_EXTENSION)
+            {
+                continue;
+            }
+            let id = name.split([' ', '.']).next().unwrap_or_default();
+            if !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) {
+                let entry = Entry {
+                    md5: None,
+                    path: Some(PathBuf::from(&*name)),
+                };
+                map.insert(id.to_owned(), entry);
+            }
+        }
+
+        if !map.is_empty() {
+            let entries: Vec<_> = map.iter().map(|(id, e)| (id.as_str(), e)).collect();
+            self.append(&entries)?;
+        }
+
+        Ok(map)
+    }
+
+    fn append(&self, entries: &[(&str, &Entry)]) -> Result<()> {
+        let mut buf = String::new();
+        for (id, entry) in entries {
+            let md5 = entry.md5.as_deref().unwrap_or("-");
+            buf.push_str(&format!("{id} {md5} {}", self.site));
+            if let Some(path) = &entry.path {
+                buf.push_str(&format!(" {}", path.display()));
+            }
+            buf.push('\n');
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut f| f.write_all(buf.as_bytes()))
+            .map_err(|e| Error::io(&self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a folder named after `test` holding empty files named `names`.
+    fn folder_with(test: &str, names: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("getbooru-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn seed_matches_whole_ids() {
+        let dir = folder_with(
+            "seed-ids",
+            &["123 title.png", "45.jpg", "7x.png", "notes.txt"],
+        );
+        let archive = Archive::new(dir.join(ARCHIVE_FILE_NAME), "gelbooru.com");
+
+        assert!(archive.contains("123").unwrap());
+        assert!(archive.contains("45").unwrap());
+        assert!(!archive.contains("12").unwrap());
+        assert!(!archive.contains("7").unwrap());
+        assert!(!archive.contains("notes").unwrap());
+
+        // The seeded entries are written, so a new archive reads them back.
+        let archive = Archive::new(dir.join(ARCHIVE_FILE_NAME), "gelbooru.com");
+        let mut saved = archive.saved_in(&dir).unwrap();
+        saved.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            saved,
+            [
+                ("123".to_owned(), dir.join("123 title.png")),
+                ("45".to_owned(), dir.join("45.jpg")),
+            ]
+        );
+    }
+
+    #[test]
+    fn seed_skips_part_files() {
+        let dir = folder_with(
+            "seed-part",
+            &["12 title.png.part", "13 title.png.part.json"],
+        );
+        let archive = Archive::new(dir.join(ARCHIVE_FILE_NAME), "gelbooru.com");
+
+        let saved = archive.saved_in(&dir).unwrap();
+        let written = dir.join(ARCHIVE_FILE_NAME).exists();
+        fs::remove_dir_all(dir).unwrap();
+        assert!(saved.is_empty());
+        assert!(!written);
+    }
+
+    #[test]
+    fn parse_keeps_known_md5() {
+        let archive = Archive::new(ARCHIVE_FILE_NAME, "gelbooru.com");
+        let map = archive.parse(
+            "12 -\n13 abc\n12 def gelbooru.com\n13 - gelbooru.com a b.png\n14 - yande.re\n\n",
+        );
+        assert_eq!(map["12"].md5.as_deref(), Some("def"));
+        assert_eq!(map["13"].md5.as_deref(), Some("abc"));
+        assert_eq!(map["13"].path.as_deref(), Some(Path::new("a b.png")));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn sites_do_not_share_ids() {
+        let dir = folder_with("sites", &[]);
+        let path = dir.join(ARCHIVE_FILE_NAME);
+        Archive::new(&path, "gelbooru.com")
+            .insert("123", None, None)
+            .unwrap();
+        Archive::new(&path, "danbooru.donmai.us")
+            .insert("45", Some("abc"), None)
+            .unwrap();
+
+        let gelbooru = Archive::new(&path, "gelbooru.com");
+        let danbooru = Archive::new(&path, "danbooru.donmai.us");
+        let found = [
+            gelbooru.contains("123").unwrap(),
+            gelbooru.contains("45").unwrap(),
+            danbooru.contains("123").unwrap(),
+            danbooru.contains("45").unwrap(),
+        ];
+        fs::remove_dir_all(dir).unwrap();
+        assert_eq!(found, [true, false, false, true]);
+    }
+
+    #[test]
+    fn saved_in_checks_files() {
+        let dir = folder_with("saved-in", &["kept.png", "deleted.png"]);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/other.png"), "").unwrap();
+        let archive = Archive::new(dir.join("sub").join(ARCHIVE_FILE_NAME), "gelbooru.com");
+        archive
+            .insert("1", None, Some(&dir.join("kept.png")))
+            .unwrap();
+        archive
+            .insert("2", None, Some(&dir.join("deleted.png")))
+            .unwrap();
+        archive
+            .insert("3", None, Some(&dir.join("sub/other.png")))
+            .unwrap();
+        archive.insert("4", Some("abc"), None).unwrap();
+        fs::remove_file(dir.join("deleted.png")).unwrap();
+
+        let archive = Archive::new(dir.join("sub").join(ARCHIVE_FILE_NAME), "gelbooru.com");
+        let mut all = archive.saved_in(&dir).unwrap();
+        all.sort();
+        let sub = archive.saved_in(&dir.join("sub")).unwrap();
+        let written = fs::read_to_string(dir.join("sub").join(ARCHIVE_FILE_NAME)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            all,
+            [
+                ("1".to_owned(), dir.join("kept.png")),
+                ("3".to_owned(), dir.join("sub/other.png")),
+            ]
+        );
+        assert_eq!(sub, [("3".to_owned(), dir.join("sub/other.png"))]);
+        // Paths within the folder of the archive are relative to it.
+        assert!(
+            written.contains("3 - gelbooru.com other.png\n"),
+            "{written}"
+        );
+        assert!(written.contains(&format!("{}", dir.join("kept.png").display())));
+    }
+}
@@ -0,0 +1,152 @@
+use regex::Regex;
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+use super::{md5_from_url, ApiFormat, Backend, Change, Credentials, Listing, Metadata, Outcome};
+use crate::{Error, Post, Result};
+
+/// Sites running Danbooru 2.
+#[derive(Debug)]
+pub struct Danbooru {
+    base_url: String,
+    credentials: Credentials,
+}
+
+impl Danbooru {
+    pub fn new(base_url: &str, credentials: Credentials) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            credentials,
+        }
+    }
+}
+
+impl Backend for Danbooru {
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn post_url(&self, id: &str) -> String {
+        format!("{}/posts/{id}", self.base_url)
+    }
+
+    fn extract_id<'a>(&self, url: &'a str) -> Option<&'a str> {
+        let re = Regex::new("/posts/([0-9]+)").unwrap();
+        let mat = re.captures(url)?.get(1)?;
+
+        Some(&url[mat.range()])
+    }
+
+    fn api_list_url(
+        &self,
+        tags: &str,
+        page: u64,
+        limit: u64,
+        _format: ApiFormat,
+    ) -> Result<String> {
+        let mut url = format!(
+            "{}/posts.json?tags={tags}&page={page}&limit={limit}",
+            self.base_url
+        );
+        if let (Some(api_key), Some(login)) = (&self.credentials.api_key, &self.credentials.user_id)
+        {
+            url.push_str(&format!("&login={login}&api_key={api_key}"));
+        }
+
+        Ok(url)
+    }
+
+    fn parse_api_list(&self, res: &str, url: &str) -> Result<Listing> {
+        let value: Value =
+            serde_json::from_str(res).map_err(|e| Error::parse(e.to_string(), url))?;
+        match value {
+            Value::Array(posts) => Ok(Listing {
+                posts: posts
+                    .into_iter()
+                    .filter_map(|p| match p {
+                        Value::Object(meta) => Some(Post::from_metadata(meta)),
+                        _ => None,
+                    })
+                    .collect::<Result<_>>()?,
+                ..Default::default()
+            }),
+            Value::Object(o) => {
+                let message = o.get("message").and_then(Value::as_str).unwrap_or_default();
+                Err(Error::auth(format!("{message} ({url})")))
+            }
+            _ => Err(Error::parse("unexpected API response", url)),
+        }
+    }
+
+    fn favorites_tags(&self) -> Option<String> {
+        Some(format!("ordfav:{}", self.credentials.user_id.as_deref()?))
+    }
+
+    fn can_change_favorites(&self) -> bool {
+        true
+    }
+
+    fn add_favorite(&self, id: &str) -> Result<Change> {
+        let mut url = format!("{}/favorites.json?post_id={id}", self.base_url);
+        if let (Some(api_key), Some(login)) = (&self.credentials.api_key, &self.credentials.user_id)
+        {
+            url.push_str(&format!("&login={login}&api_key={api_key}"));
+        }
+
+        Ok(Change {
+            method: Method::POST,
+            url,
+        })
+    }
+
+    fn remove_favorite(&self, id: &str) -> Result<Change> {
+        let mut url = format!("{}/favorites/{id}.json", self.base_url);
+        if let (Some(api_key), Some(login)) = (&self.credentials.api_key, &self.credentials.user_id)
+        {
+            url.push_str(&format!("?login={login}&api_key={api_key}"));
+        }
+
+        Ok(Change {
+            method: Method::DELETE,
+            url,
+        })
+    }
+
+    /// The site refuses to favorite a post twice with `422 Unprocessable
+    /// Entity`.
+    fn parse_add_favorite(&self, res: Result<String>, _url: &str) -> Result<Outcome> {
+        match res {
+            Ok(_) => Ok(Outcome::Done),
+            Err(Error::Status { status, .. }) if status == StatusCode::UNPROCESSABLE_ENTITY => {
+                Ok(Outcome::Unchanged)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Extract the post from the data attributes of the image container.
+    fn extract_post(&self, res: &str, src: &str) -> Result<Post> {
+        let doc = scraper::Html::parse_document(res);
+        let selector = scraper::Selector::parse("section.image-container").unwrap();
+        let section = doc
+            .select(&selector)
+            .next()
+            .ok_or_else(|| Error::not_found("image container", src))?;
+
+        let mut meta = Metadata::new();
+        for (name, value) in section.value().attrs() {
+            if let Some(name) = name.strip_prefix("data-") {
+                meta.insert(name.replace('-', "_"), value.into());
+            }
+        }
+        let file_url = meta
+            .get("file_url")
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::not_found("file url", src))?;
+        if let Some(md5) = md5_from_url(file_url) {
+            meta.insert("md5".into(), md5.into());
+        }
+
+        Post::from_metadata(meta)
+    }
+}
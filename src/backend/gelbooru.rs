@@ -0,0 +1,521 @@
+use regex::Regex;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{
+    host, md5_from_url, sidebar_tag, ApiFormat, Backend, Change, Credentials, Listing, Metadata,
+    Outcome,
+};
+use crate::{Error, Post, Result};
+
+const PID_STEP_VIEW: u64 = 50;
+/// Path of a file within a page, up to the end of the attribute holding it.
+const FILE_PATH_PATTERN: &str = r#"[^"'\s<>]+\.[A-Za-z0-9]+"#;
+const PID_STEP_LIST: u64 = 42;
+
+/// Response of the API with `json=1`.
+///
+/// Gelbooru wraps posts along with the position of the page, while older
+/// forks answer with a bare array.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonList {
+    Paged {
+        #[serde(rename = "@attributes")]
+        attributes: JsonAttributes,
+        #[serde(default)]
+        post: Vec<Metadata>,
+    },
+    Refused {
+        #[serde(alias = "reason")]
+        message: String,
+    },
+    Bare(Vec<Metadata>),
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonAttributes {
+    offset: u64,
+    count: u64,
+}
+
+/// Sites running Gelbooru 0.2 or a compatible fork.
+#[derive(Debug)]
+pub struct Gelbooru {
+    base_url: String,
+    file_url: Regex,
+    credentials: Credentials,
+    /// Whether the API refuses requests without `api_key` and `user_id`.
+    require_key: bool,
+}
+
+impl Gelbooru {
+    /// Create a backend for the site at `base_url`, whose files are served
+    /// at urls matching `file_url_pattern`.
+    pub fn new(base_url: &str, file_url_pattern: &str, credentials: Credentials) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            file_url: Regex::new(file_url_pattern).expect("invalid file url pattern"),
+            credentials,
+            require_key: false,
+        }
+    }
+
+    /// Create a backend for a self-hosted site at `base_url`, which serves
+    /// files from `/images/` on its own host.
+    pub fn self_hosted(base_url: &str, credentials: Credentials) -> Self {
+        let pattern = format!(
+            r"https?://{}//?images/{FILE_PATH_PATTERN}",
+            regex::escape(host(base_url))
+        );
+
+        Self::new(base_url, &pattern, credentials)
+    }
+
+    /// Also accept files served from given hosts.
+    pub fn with_cdn_hosts<S: AsRef<str>>(mut self, hosts: &[S]) -> Self {
+        if !hosts.is_empty() {
+            let hosts: Vec<_> = hosts.iter().map(AsRef::as_ref).collect();
+            let pattern = format!("{}|{}", self.file_url.as_str(), Self::hosts_pattern(&hosts));
+            self.file_url = Regex::new(&pattern).expect("invalid file url pattern");
+        }
+
+        self
+    }
+
+    fn hosts_pattern(hosts: &[&str]) -> String {
+        let hosts: Vec<_> = hosts.iter().map(|h| regex::escape(h)).collect();
+
+        format!(r"https?://({})/{FILE_PATH_PATTERN}", hosts.join("|"))
+    }
+
+    pub fn gelbooru_com(credentials: Credentials) -> Self {
+        Self {
+            require_key: true,
+            ..Self::new(
+                "https://gelbooru.com",
+                &format!(r"https://(img[123]|video-cdn[123])\.gelbooru\.com/{FILE_PATH_PATTERN}"),
+                credentials,
+            )
+        }
+    }
+
+    pub fn safebooru_org(credentials: Credentials) -> Self {
+        Self::new(
+            "https://safebooru.org",
+            &format!(r"https://safebooru\.org//?images/{FILE_PATH_PATTERN}"),
+            credentials,
+        )
+    }
+
+    pub fn rule34_xxx(credentials: Credentials) -> Self {
+        Self::new(
+            "https://rule34.xxx",
+            &format!(r"https://([a-z0-9-]+\.)?rule34\.xxx//?images/{FILE_PATH_PATTERN}"),
+            credentials,
+        )
+    }
+
+    /// Favorites are changed through pages, which need the cookies logging
+    /// in.
+    fn check_cookies(&self) -> Result<()> {
+        match (&self.credentials.user_id, &self.credentials.pass_hash) {
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err(Error::auth(
+                "user_id and pass_hash are needed to change favorites",
+            )),
+        }
+    }
+
+    /// Build a post out of the fields the site exposes.
+    fn post(&self, mut meta: Metadata) -> Result<Post> {
+        // Gelbooru 0.2 rates posts `s`afe rather than `s`ensitive.
+        if meta.get("rating").and_then(Value::as_str) == Some("s") {
+            meta.insert("rating".into(), "general".into());
+        }
+        let mut post = Post::from_metadata(meta)?;
+
+        // Older forks only tell where the file is stored.
+        if post.file_url.is_none() {
+            let directory = post.extra.get("directory").and_then(Value::as_str);
+            let image = post.extra.get("image").and_then(Value::as_str);
+            if let (Some(directory), Some(image)) = (directory, image) {
+                post.file_url = Some(format!("{}/images/{directory}/{image}", self.base_url));
+            }
+        }
+        if post.md5.is_none() {
+            post.md5 = post
+                .file_url
+                .as_deref()
+                .and_then(md5_from_url)
+                .map(str::to_owned);
+        }
+
+        Ok(post)
+    }
+
+    fn parse_api_list_xml(&self, res: &str, url: &str) -> Result<Listing> {
+        let doc = roxmltree::Document::parse(res).map_err(|e| Error::parse(e.to_string(), url))?;
+        let root = doc.root_element();
+        if root.has_tag_name("response") && root.attribute("success") == Some("false") {
+            let reason = root.attribute("reason").unwrap_or_default();
+            return Err(refusal(reason, url));
+        }
+
+        let posts = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("post"))
+            .map(|post| {
+                // Older forks put fields into attributes, newer ones into
+                // child elements.
+                let attributes = post
+                    .attributes()
+                    .map(|a| (a.name().to_owned(), Value::from(a.value())));
+                let children = post.children().filter(|n| n.is_element()).map(|n| {
+                    let value = n.text().unwrap_or_default();
+                    (n.tag_name().name().to_owned(), Value::from(value))
+                });
+
+                self.post(attributes.chain(children).collect())
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Listing {
+            posts,
+            count: root.attribute("count").and_then(|c| c.parse().ok()),
+            offset: root.attribute("offset").and_then(|o| o.parse().ok()),
+        })
+    }
+
+    fn parse_api_list_json(&self, res: &str, url: &str) -> Result<Listing> {
+        // Some forks answer an empty search with an empty body.
+        if res.is_empty() {
+            return Ok(Listing::default());
+        }
+
+        let list: JsonList =
+            serde_json::from_str(res).map_err(|e| Error::parse(e.to_string(), url))?;
+        let (posts, count, offset) = match list {
+            JsonList::Paged { attributes, post } => {
+                (post, Some(attributes.count), Some(attributes.offset))
+            }
+            JsonList::Refused { message } => return Err(refusal(&message, url)),
+            JsonList::Bare(posts) => (posts, None, None),
+        };
+
+        let posts = posts
+            .into_iter()
+            .map(|meta| self.post(meta))
+            .collect::<Result<_>>()?;
+
+        Ok(Listing {
+            posts,
+            count,
+            offset,
+        })
+    }
+}
+
+impl Backend for Gelbooru {
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn post_url(&self, id: &str) -> String {
+        format!("{}/index.php?page=post&s=view&id={id}", self.base_url)
+    }
+
+    fn extract_id<'a>(&self, url: &'a str) -> Option<&'a str> {
+        let re = Regex::new("id=([0-9]+)&*").unwrap();
+        let mat = re.captures(url)?.get(1)?;
+
+        Some(&url[mat.range()])
+    }
+
+    fn api_list_url(&self, tags: &str, page: u64, limit: u64, format: ApiFormat) -> Result<String> {
+        let mut url = format!(
+            "{}/index.php?page=dapi&s=post&q=index&tags={tags}&pid={}&limit={limit}",
+            self.base_url,
+            page - 1,
+        );
+        if format == ApiFormat::Json {
+            url.push_str("&json=1");
+        }
+        match (&self.credentials.api_key, &self.credentials.user_id) {
+            (Some(api_key), Some(user_id)) => {
+                url.push_str(&format!("&api_key={api_key}&user_id={user_id}"));
+            }
+            (None, _) if self.require_key => return Err(Error::auth("api_key is not specified")),
+            (_, None) if self.require_key => return Err(Error::auth("user_id is not specified")),
+            _ => {}
+        }
+
+        Ok(url)
+    }
+
+    fn parse_api_list(&self, res: &str, url: &str) -> Result<Listing> {
+        let res = res.trim();
+        // Errors are reported in XML whatever the format asked for.
+        if res.starts_with('<') {
+            self.parse_api_list_xml(res, url)
+        } else {
+            self.parse_api_list_json(res, url)
+        }
+    }
+
+    fn html_list_url(&self, tags: &str, page: u64) -> Option<String> {
+        Some(format!(
+            "{}/index.php?page=post&s=list&tags={tags}&pid={}",
+            self.base_url,
+            (page - 1) * PID_STEP_LIST,
+        ))
+    }
+
+    fn parse_html_list(&self, res: &str, url: &str) -> Result<Vec<String>> {
+        let list = scraper::Html::parse_document(res);
+        let selector =
+            scraper::Selector::parse("article.thumbnail-preview a, span.thumb a").unwrap();
+
+        list.select(&selector)
+            .map(|a| {
+                let href = a
+                    .value()
+                    .attr("href")
+                    .ok_or_else(|| Error::not_found("href in thumbnail element", url))?;
+                let id = self
+                    .extract_id(href)
+                    .ok_or_else(|| Error::not_found("post id", href))?;
+
+                Ok(id.to_owned())
+            })
+            .collect()
+    }
+
+    fn favorites_url(&self, page: u64) -> Option<String> {
+        Some(format!(
+            "{}/index.php?page=favorites&s=view&id={}&pid={}",
+            self.base_url,
+            self.credentials.user_id.as_deref()?,
+            (page - 1) * PID_STEP_VIEW,
+        ))
+    }
+
+    fn parse_favorites(&self, res: &str, _url: &str) -> Result<Vec<String>> {
+        let page = scraper::Html::parse_document(res);
+        let selector =
+            scraper::Selector::parse("span.thumb a, article.thumbnail-preview a").unwrap();
+
+        // Thumbnails also hold a link removing the post from favorites.
+        let mut ids: Vec<String> = Vec::new();
+        for href in page
+            .select(&selector)
+            .filter_map(|a| a.value().attr("href"))
+        {
+            if let Some(id) = self.extract_id(href) {
+                if !ids.iter().any(|i| i == id) {
+                    ids.push(id.to_owned());
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn api_favorites_url(&self, page: u64) -> Option<String> {
+        let mut url = format!(
+            "{}/index.php?page=dapi&s=favorite&q=index&id={}&pid={}&limit={PID_STEP_VIEW}",
+            self.base_url,
+            self.credentials.user_id.as_deref()?,
+            page - 1,
+        );
+        if let (Some(api_key), Some(user_id)) =
+            (&self.credentials.api_key, &self.credentials.user_id)
+        {
+            url.push_str(&format!("&api_key={api_key}&user_id={user_id}"));
+        }
+
+        Some(url)
+    }
+
+    fn can_change_favorites(&self) -> bool {
+        true
+    }
+
+    fn add_favorite(&self, id: &str) -> Result<Change> {
+        self.check_cookies()?;
+        Ok(Change {
+            method: Method::GET,
+            url: format!("{}/public/addfav.php?id={id}", self.base_url),
+        })
+    }
+
+    /// The site answers `1` if the post is in favorites already, `2` if the
+    /// cookies do not log in, and `3` once added.
+    fn parse_add_favorite(&self, res: Result<String>, url: &str) -> Result<Outcome> {
+        match res?.trim() {
+            "1" => Ok(Outcome::Unchanged),
+            "2" => Err(Error::auth(format!("not logged in ({url})"))),
+            "3" => Ok(Outcome::Done),
+            _ => Err(Error::parse("unexpected answer to adding a favorite", url)),
+        }
+    }
+
+    fn remove_favorite(&self, id: &str) -> Result<Change> {
+        self.check_cookies()?;
+        Ok(Change {
+            method: Method::GET,
+            url: format!(
+                "{}/index.php?page=favorites&s=delete&id={id}",
+                self.base_url
+            ),
+        })
+    }
+
+    /// The site sends the favorites page back, or the login page if the
+    /// cookies do not log in.
+    fn parse_remove_favorite(&self, res: Result<String>, url: &str) -> Result<Outcome> {
+        let res = res?;
+        let page = scraper::Html::parse_document(&res);
+        let selector = scraper::Selector::parse("form[action*='s=login']").unwrap();
+        if page.select(&selector).next().is_some() {
+            return Err(Error::auth(format!("not logged in ({url})")));
+        }
+
+        Ok(Outcome::Done)
+    }
+
+    /// Parse `<favorite favorite="post id" />` elements, or their JSON
+    /// counterpart.
+    fn parse_api_favorites(&self, res: &str, url: &str) -> Result<Vec<String>> {
+        let res = res.trim();
+        if res.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if !res.starts_with('<') {
+            let value: Value =
+                serde_json::from_str(res).map_err(|e| Error::parse(e.to_string(), url))?;
+            let list = match &value {
+                Value::Object(o) => o.get("favorite").unwrap_or(&Value::Null),
+                value => value,
+            };
+            let list = match list {
+                Value::Array(a) => a.as_slice(),
+                Value::Object(_) => std::slice::from_ref(list),
+                _ => &[],
+            };
+
+            return Ok(list
+                .iter()
+                .filter_map(|f| match f.get("favorite").or_else(|| f.get("post_id"))? {
+                    Value::String(id) => Some(id.clone()),
+                    Value::Number(id) => Some(id.to_string()),
+                    _ => None,
+                })
+                .collect());
+        }
+
+        let doc = roxmltree::Document::parse(res).map_err(|e| Error::parse(e.to_string(), url))?;
+        let root = doc.root_element();
+        if root.has_tag_name("response") && root.attribute("success") == Some("false") {
+            let reason = root.attribute("reason").unwrap_or_default();
+            return Err(refusal(reason, url));
+        }
+
+        Ok(doc
+            .descendants()
+            .filter(|n| n.has_tag_name("favorite"))
+            .filter_map(|f| {
+                let id = f.attribute("favorite").or_else(|| {
+                    f.children()
+                        .find(|n| n.has_tag_name("favorite"))
+                        .and_then(|n| n.text())
+                })?;
+                Some(id.trim().to_owned())
+            })
+            .collect())
+    }
+
+    /// Extract the file url from given post page, and the metadata from its
+    /// sidebar.
+    fn extract_post(&self, res: &str, src: &str) -> Result<Post> {
+        let file_url = self
+            .file_url
+            .find(res)
+            .ok_or_else(|| Error::not_found("file url", src))?
+            .as_str();
+
+        let doc = scraper::Html::parse_document(res);
+        let li_selector =
+            scraper::Selector::parse("ul#tag-list li, ul#tag-sidebar li, div#stats li").unwrap();
+        let a_selector = scraper::Selector::parse("a").unwrap();
+
+        let mut meta = Metadata::new();
+        let mut tags = Vec::new();
+        for li in doc.select(&li_selector) {
+            if let Some(kind) = li
+                .value()
+                .classes()
+                .find_map(|c| c.strip_prefix("tag-type-"))
+            {
+                tags.extend(sidebar_tag(li, kind, &mut meta));
+                continue;
+            }
+
+            let text = li.text().collect::<String>();
+            let Some((key, value)) = text.trim().split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "Id" => {
+                    meta.insert("id".into(), value.into());
+                }
+                "Posted" => {
+                    let date = value.split("by").next().unwrap_or_default().trim();
+                    meta.insert("created_at".into(), date.into());
+                }
+                "Size" => {
+                    if let Some((width, height)) = value.split_once('x') {
+                        meta.insert("width".into(), width.trim().into());
+                        meta.insert("height".into(), height.trim().into());
+                    }
+                }
+                "Rating" => {
+                    meta.insert("rating".into(), value.to_lowercase().into());
+                }
+                "Score" => {
+                    let score = value.split_whitespace().next().unwrap_or_default();
+                    meta.insert("score".into(), score.into());
+                }
+                "Source" => {
+                    let source = li
+                        .select(&a_selector)
+                        .find_map(|a| a.value().attr("href"))
+                        .unwrap_or(value);
+                    meta.insert("source".into(), source.into());
+                }
+                _ => {}
+            }
+        }
+        meta.insert("tags".into(), tags.join(" ").into());
+        if let Some(id) = self.extract_id(src) {
+            meta.insert("id".into(), id.into());
+        }
+        meta.insert("file_url".into(), file_url.into());
+
+        self.post(meta)
+    }
+}
+
+/// Build an error out of the reason the API gives for refusing a request.
+fn refusal(reason: &str, url: &str) -> Error {
+    if reason.to_lowercase().contains("limit") {
+        Error::RateLimited {
+            url: url.to_owned(),
+        }
+    } else {
+        Error::auth(format!("{reason} ({url})"))
+    }
+}
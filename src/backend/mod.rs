@@ -0,0 +1,291 @@
+//! Sites a [`Session`](crate::Session) can work with.
+//!
+//! Every site-specific detail, i.e. urls, response formats and page layouts,
+//! lives behind the [`Backend`] trait, so that the same session logic drives
+//! Gelbooru 0.2, Danbooru and Moebooru style sites alike.
+
+use std::{fmt, str::FromStr};
+
+use serde_json::{Map, Value};
+
+use crate::{Error, Post, Result};
+
+mod danbooru;
+mod gelbooru;
+mod moebooru;
+
+pub use danbooru::Danbooru;
+pub use gelbooru::Gelbooru;
+pub use moebooru::Moebooru;
+
+/// Post metadata, keyed by the field names of the site.
+pub type Metadata = Map<String, Value>;
+
+/// Extract md5 from given file url, which boorus name files after.
+pub fn md5_from_url(file_url: &str) -> Option<&str> {
+    let name = file_url.rsplit('/').next()?;
+    let stem = name.split('.').next()?;
+
+    (stem.len() == 32 && stem.bytes().all(|b| b.is_ascii_hexdigit())).then_some(stem)
+}
+
+/// Get the host, and the port if any, of given url.
+pub(crate) fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+
+    rest.split('/').next().unwrap_or(rest)
+}
+
+/// Read the tag of given item of the tag sidebar of a post page, of class
+/// `tag-type-<kind>`, into the `tags_<kind>` field of `meta`.
+///
+/// Returns the tag, if the item links to a search of it.
+pub(crate) fn sidebar_tag(
+    li: scraper::ElementRef,
+    kind: &str,
+    meta: &mut Metadata,
+) -> Option<String> {
+    let a_selector = scraper::Selector::parse("a").unwrap();
+    let tag = li
+        .select(&a_selector)
+        .filter(|a| a.value().attr("href").is_some_and(|h| h.contains("tags=")))
+        .last()?;
+    let tag = tag.text().collect::<String>().trim().replace(' ', "_");
+
+    let key = format!("tags_{kind}");
+    match meta.get_mut(&key) {
+        Some(Value::Array(a)) => a.push(Value::from(tag.as_str())),
+        _ => {
+            meta.insert(key, Value::from(vec![tag.as_str()]));
+        }
+    }
+
+    Some(tag)
+}
+
+/// Format of the responses requested from an API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiFormat {
+    /// JSON, on sites which can answer with it.
+    #[default]
+    Json,
+    Xml,
+}
+
+/// One page of an API listing.
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub posts: Vec<Post>,
+    /// Number of posts matching the search in total, if known.
+    pub count: Option<u64>,
+    /// Number of posts before this page, if known.
+    pub offset: Option<u64>,
+}
+
+impl Listing {
+    /// Whether no more posts follow this page.
+    pub fn is_last(&self) -> bool {
+        match (self.count, self.offset) {
+            _ if self.posts.is_empty() => true,
+            (Some(count), Some(offset)) => offset + self.posts.len() as u64 >= count,
+            _ => false,
+        }
+    }
+}
+
+/// A request changing the account of current user, e.g. its favorites.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub method: reqwest::Method,
+    pub url: String,
+}
+
+/// What a change did, as told by the site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// Nothing had to change, e.g. the post was in favorites already.
+    Unchanged,
+}
+
+/// Account details a backend may attach to its requests.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub api_key: Option<String>,
+    /// User id on Gelbooru 0.2 sites, login name elsewhere.
+    pub user_id: Option<String>,
+    pub pass_hash: Option<String>,
+}
+
+/// A site the session fetches posts from.
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Url of the home page, where cookies are set.
+    fn base_url(&self) -> &str;
+
+    /// Url of the page of post with given id.
+    fn post_url(&self, id: &str) -> String;
+
+    /// Extract post id from the url of a post page.
+    fn extract_id<'a>(&self, url: &'a str) -> Option<&'a str>;
+
+    /// Url of the API listing of posts matching `tags`,
+    /// at `page` (starting from 1) holding `limit` posts,
+    /// in given format if the site supports it.
+    fn api_list_url(&self, tags: &str, page: u64, limit: u64, format: ApiFormat) -> Result<String>;
+
+    /// Parse posts out of the response of an API listing at `url`.
+    fn parse_api_list(&self, res: &str, url: &str) -> Result<Listing>;
+
+    /// Url of the HTML listing of posts matching `tags`, at `page`
+    /// (starting from 1), if the site can be scraped without its API.
+    fn html_list_url(&self, _tags: &str, _page: u64) -> Option<String> {
+        None
+    }
+
+    /// Extract post ids out of the response of an HTML listing at `url`.
+    fn parse_html_list(&self, _res: &str, url: &str) -> Result<Vec<String>> {
+        Err(Error::unsupported(
+            format!("HTML listing ({url})"),
+            self.base_url(),
+        ))
+    }
+
+    /// Url of the favorites of current user, at `page` (starting from 1).
+    fn favorites_url(&self, _page: u64) -> Option<String> {
+        None
+    }
+
+    /// Extract post ids out of the response of a favorites page at `url`.
+    fn parse_favorites(&self, _res: &str, url: &str) -> Result<Vec<String>> {
+        Err(Error::unsupported(
+            format!("favorites pages ({url})"),
+            self.base_url(),
+        ))
+    }
+
+    /// Url of the API listing of the favorites of current user, at `page`
+    /// (starting from 1), if the site has one. Pages hold as many posts as
+    /// the ones at `favorites_url`.
+    fn api_favorites_url(&self, _page: u64) -> Option<String> {
+        None
+    }
+
+    /// Extract post ids out of the response of an API listing of favorites
+    /// at `url`.
+    fn parse_api_favorites(&self, _res: &str, url: &str) -> Result<Vec<String>> {
+        Err(Error::unsupported(
+            format!("favorites API ({url})"),
+            self.base_url(),
+        ))
+    }
+
+    /// Whether favorites can be added and removed, through `add_favorite`
+    /// and `remove_favorite`.
+    fn can_change_favorites(&self) -> bool {
+        false
+    }
+
+    /// Request adding the post with given id to the favorites of current
+    /// user.
+    fn add_favorite(&self, _id: &str) -> Result<Change> {
+        Err(Error::unsupported("adding favorites", self.base_url()))
+    }
+
+    /// Tell what adding a favorite did from the response body, or from the
+    /// error the request at `url` ended with, some sites answering with an
+    /// error status when the post is in favorites already.
+    fn parse_add_favorite(&self, res: Result<String>, _url: &str) -> Result<Outcome> {
+        res.map(|_| Outcome::Done)
+    }
+
+    /// Request removing the post with given id from the favorites of current
+    /// user.
+    fn remove_favorite(&self, _id: &str) -> Result<Change> {
+        Err(Error::unsupported("removing favorites", self.base_url()))
+    }
+
+    /// Tell what removing a favorite did from the response body, or from the
+    /// error the request at `url` ended with.
+    fn parse_remove_favorite(&self, res: Result<String>, _url: &str) -> Result<Outcome> {
+        res.map(|_| Outcome::Done)
+    }
+
+    /// Tags searching the favorites of current user, on sites which can
+    /// list them like any other posts.
+    fn favorites_tags(&self) -> Option<String> {
+        None
+    }
+
+    /// Extract the post, with its file url and as much metadata as possible,
+    /// from the response of the post page at `src`.
+    fn extract_post(&self, res: &str, src: &str) -> Result<Post>;
+}
+
+/// Sites with a built-in backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Site {
+    #[default]
+    Gelbooru,
+    Safebooru,
+    Rule34,
+    Danbooru,
+    Konachan,
+    Yandere,
+}
+
+impl Site {
+    /// Create the backend of this site.
+    ///
+    /// If `base_url` is given, the backend talks to a mirror or a self-hosted
+    /// instance running the same software at that url instead. Files are
+    /// also accepted from `cdn_hosts`, besides the usual hosts of the site.
+    pub fn backend<S: AsRef<str>>(
+        self,
+        base_url: Option<&str>,
+        cdn_hosts: &[S],
+        credentials: Credentials,
+    ) -> Box<dyn Backend> {
+        match (self, base_url) {
+            (Self::Gelbooru | Self::Safebooru | Self::Rule34, Some(base_url)) => {
+                Box::new(Gelbooru::self_hosted(base_url, credentials).with_cdn_hosts(cdn_hosts))
+            }
+            (Self::Gelbooru, None) => {
+                Box::new(Gelbooru::gelbooru_com(credentials).with_cdn_hosts(cdn_hosts))
+            }
+            (Self::Safebooru, None) => {
+                Box::new(Gelbooru::safebooru_org(credentials).with_cdn_hosts(cdn_hosts))
+            }
+            (Self::Rule34, None) => {
+                Box::new(Gelbooru::rule34_xxx(credentials).with_cdn_hosts(cdn_hosts))
+            }
+            (Self::Danbooru, _) => Box::new(Danbooru::new(
+                base_url.unwrap_or("https://danbooru.donmai.us"),
+                credentials,
+            )),
+            (Self::Konachan, _) => Box::new(Moebooru::new(
+                base_url.unwrap_or("https://konachan.com"),
+                credentials,
+            )),
+            (Self::Yandere, _) => Box::new(Moebooru::new(
+                base_url.unwrap_or("https://yande.re"),
+                credentials,
+            )),
+        }
+    }
+}
+
+impl FromStr for Site {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "gelbooru" | "gelbooru.com" => Ok(Self::Gelbooru),
+            "safebooru" | "safebooru.org" => Ok(Self::Safebooru),
+            "rule34" | "rule34.xxx" => Ok(Self::Rule34),
+            "danbooru" | "danbooru.donmai.us" => Ok(Self::Danbooru),
+            "konachan" | "konachan.com" => Ok(Self::Konachan),
+            "yandere" | "yande.re" => Ok(Self::Yandere),
+            _ => Err(Error::parse("unknown site", s)),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use regex::Regex;
+use reqwest::Method;
+use serde_json::Value;
+
+use super::{sidebar_tag, ApiFormat, Backend, Change, Credentials, Listing, Metadata};
+use crate::{Error, Post, Result};
+
+/// Sites running Moebooru, such as konachan.com and yande.re.
+#[derive(Debug)]
+pub struct Moebooru {
+    base_url: String,
+    credentials: Credentials,
+}
+
+impl Moebooru {
+    pub fn new(base_url: &str, credentials: Credentials) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            credentials,
+        }
+    }
+
+    /// Build a post out of the fields the site exposes.
+    fn post(mut meta: Metadata) -> Result<Post> {
+        // Moebooru rates posts `s`afe rather than `s`ensitive.
+        if meta.get("rating").and_then(Value::as_str) == Some("s") {
+            meta.insert("rating".into(), "general".into());
+        }
+
+        Post::from_metadata(meta)
+    }
+}
+
+impl Backend for Moebooru {
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn post_url(&self, id: &str) -> String {
+        format!("{}/post/show/{id}", self.base_url)
+    }
+
+    fn extract_id<'a>(&self, url: &'a str) -> Option<&'a str> {
+        let re = Regex::new("/post/show/([0-9]+)").unwrap();
+        let mat = re.captures(url)?.get(1)?;
+
+        Some(&url[mat.range()])
+    }
+
+    fn api_list_url(
+        &self,
+        tags: &str,
+        page: u64,
+        limit: u64,
+        _format: ApiFormat,
+    ) -> Result<String> {
+        let mut url = format!(
+            "{}/post.json?tags={tags}&page={page}&limit={limit}",
+            self.base_url
+        );
+        if let (Some(login), Some(pass_hash)) =
+            (&self.credentials.user_id, &self.credentials.pass_hash)
+        {
+            url.push_str(&format!("&login={login}&password_hash={pass_hash}"));
+        }
+
+        Ok(url)
+    }
+
+    fn parse_api_list(&self, res: &str, url: &str) -> Result<Listing> {
+        let value: Value =
+            serde_json::from_str(res).map_err(|e| Error::parse(e.to_string(), url))?;
+        match value {
+            Value::Array(posts) => Ok(Listing {
+                posts: posts
+                    .into_iter()
+                    .filter_map(|p| match p {
+                        Value::Object(meta) => Some(Self::post(meta)),
+                        _ => None,
+                    })
+                    .collect::<Result<_>>()?,
+                ..Default::default()
+            }),
+            Value::Object(o) => {
+                let reason = o.get("reason").and_then(Value::as_str).unwrap_or_default();
+                Err(Error::auth(format!("{reason} ({url})")))
+            }
+            _ => Err(Error::parse("unexpected API response", url)),
+        }
+    }
+
+    fn favorites_tags(&self) -> Option<String> {
+        Some(format!(
+            "vote:3:{}+order:vote",
+            self.credentials.user_id.as_deref()?
+        ))
+    }
+
+    fn can_change_favorites(&self) -> bool {
+        true
+    }
+
+    /// Vote for the post with the highest score, which favorites it.
+    fn add_favorite(&self, id: &str) -> Result<Change> {
+        let mut url = format!("{}/post/vote.json?id={id}&score=3", self.base_url);
+        if let (Some(login), Some(pass_hash)) =
+            (&self.credentials.user_id, &self.credentials.pass_hash)
+        {
+            url.push_str(&format!("&login={login}&password_hash={pass_hash}"));
+        }
+
+        Ok(Change {
+            method: Method::POST,
+            url,
+        })
+    }
+
+    /// Take the vote for the post back, which unfavorites it.
+    fn remove_favorite(&self, id: &str) -> Result<Change> {
+        let mut url = format!("{}/post/vote.json?id={id}&score=0", self.base_url);
+        if let (Some(login), Some(pass_hash)) =
+            (&self.credentials.user_id, &self.credentials.pass_hash)
+        {
+            url.push_str(&format!("&login={login}&password_hash={pass_hash}"));
+        }
+
+        Ok(Change {
+            method: Method::POST,
+            url,
+        })
+    }
+
+    /// Extract the post from the JSON the page registers it with, and the
+    /// file url from the download link.
+    fn extract_post(&self, res: &str, src: &str) -> Result<Post> {
+        let re = Regex::new(r"Post\.register\((\{.*?\})\)").unwrap();
+        let mut meta: Metadata = re
+            .captures(res)
+            .and_then(|c| serde_json::from_str(c.get(1)?.as_str()).ok())
+            .unwrap_or_default();
+
+        let doc = scraper::Html::parse_document(res);
+        let selector = scraper::Selector::parse("a#highres, a#png").unwrap();
+        let file_url = doc
+            .select(&selector)
+            .find_map(|a| a.value().attr("href"))
+            .ok_or_else(|| Error::not_found("file url", src))?;
+        meta.insert("file_url".into(), file_url.into());
+
+        let li_selector = scraper::Selector::parse("ul#tag-sidebar li").unwrap();
+        for li in doc.select(&li_selector) {
+            if let Some(kind) = li
+                .value()
+                .classes()
+                .find_map(|c| c.strip_prefix("tag-type-"))
+            {
+                sidebar_tag(li, kind, &mut meta);
+            }
+        }
+        if let Some(id) = self.extract_id(src) {
+            meta.entry("id").or_insert(id.into());
+        }
+
+        Self::post(meta)
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fmt, io};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by a [`Session`](crate::Session).
+///
+/// Every variant carries the URL, post id or path involved, so callers can
+/// decide whether to retry, skip or abort.
+#[derive(Debug)]
+pub enum Error {
+    /// A request could not be sent, or its body could not be read.
+    /// `url` is empty if the HTTP client itself could not be built.
+    Http { url: String, source: reqwest::Error },
+    /// The server answered with an unexpected status code.
+    Status {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+    /// A response or an input file is not in the expected format.
+    Parse { what: String, context: String },
+    /// Reading from or writing to the local filesystem failed.
+    Io { path: String, source: io::Error },
+    /// Credentials needed for the action are missing or were refused.
+    Auth { what: String },
+    /// The connection was closed before the whole body was received.
+    Incomplete {
+        url: String,
+        expected: u64,
+        received: u64,
+    },
+    /// The md5 of a downloaded file is not the one of the post.
+    Checksum {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    /// The file changed on the server while it was being downloaded.
+    Changed { url: String },
+    /// The site refused the request because a rate or daily limit was hit.
+    RateLimited { url: String },
+    /// The requested post, element or field does not exist.
+    NotFound { what: String, context: String },
+    /// The site does not provide what the action needs.
+    Unsupported { what: String, site: String },
+    /// The action would remove something, and the session is not set to
+    /// `force` it.
+    Unconfirmed { what: String },
+    /// The WebDriver session could not be started or a command failed.
+    WebDriver {
+        context: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl Error {
+    pub(crate) fn http(url: &str, source: reqwest::Error) -> Self {
+        Self::Http {
+            url: url.to_owned(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(what: impl Into<String>, context: &str) -> Self {
+        Self::Parse {
+            what: what.into(),
+            context: context.to_owned(),
+        }
+    }
+
+    pub(crate) fn io(path: impl AsRef<std::path::Path>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.as_ref().display().to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn auth(what: impl Into<String>) -> Self {
+        Self::Auth { what: what.into() }
+    }
+
+    pub(crate) fn not_found(what: impl Into<String>, context: &str) -> Self {
+        Self::NotFound {
+            what: what.into(),
+            context: context.to_owned(),
+        }
+    }
+
+    pub(crate) fn unsupported(what: impl Into<String>, site: &str) -> Self {
+        Self::Unsupported {
+            what: what.into(),
+            site: site.to_owned(),
+        }
+    }
+
+    pub(crate) fn webdriver(
+        context: &str,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        Self::WebDriver {
+            context: context.to_owned(),
+            source: Box::new(source),
+        }
+    }
+
+    /// Whether the site refused to answer, because a limit was hit or the
+    /// credentials were not accepted, rather than failed to.
+    pub fn is_refusal(&self) -> bool {
+        matches!(self, Self::RateLimited { .. } | Self::Auth { .. })
+    }
+
+    /// Build an error out of a non-success status code.
+    pub(crate) fn from_status(url: &str, status: reqwest::StatusCode) -> Self {
+        use reqwest::StatusCode;
+
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited {
+                url: url.to_owned(),
+            },
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth {
+                what: format!("access to {url} was refused ({status})"),
+            },
+            StatusCode::NOT_FOUND => Self::not_found("page", url),
+            _ => Self::Status {
+                url: url.to_owned(),
+                status,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http { url, source } if url.is_empty() => {
+                write!(f, "HTTP client error: {source}")
+            }
+            Self::Http { url, source } => write!(f, "request to {url} failed: {source}"),
+            Self::Status { url, status } => write!(f, "{url} returned {status}"),
+            Self::Parse { what, context } => write!(f, "{what} ({context})"),
+            Self::Io { path, source } => write!(f, "{path}: {source}"),
+            Self::Auth { what } => write!(f, "authentication error: {what}"),
+            Self::Incomplete {
+                url,
+                expected,
+                received,
+            } => write!(f, "{url} ended after {received} of {expected} bytes"),
+            Self::Checksum {
+                url,
+                expected,
+                actual,
+            } => write!(f, "{url} has md5 {actual} instead of {expected}"),
+            Self::Changed { url } => write!(f, "{url} changed during the download"),
+            Self::RateLimited { url } => write!(f, "rate limit reached when requesting {url}"),
+            Self::NotFound { what, context } => write!(f, "cannot find {what} ({context})"),
+            Self::Unsupported { what, site } => write!(f, "{site} does not support {what}"),
+            Self::Unconfirmed { what } => write!(f, "refusing to {what} without 'force'"),
+            Self::WebDriver { context, source } => {
+                write!(f, "WebDriver error at {context}: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http { source, .. } => Some(source),
+            Self::Io { source, .. } => Some(source),
+            Self::WebDriver { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Write},
+    pin::pin,
+    str::FromStr,
+};
+
+use futures_util::{Stream, TryStreamExt};
+use serde::Serialize;
+
+use crate::{
+    backend::md5_from_url, tag_list::Destination, Action, Error, Listed, Post, Result, Session,
+};
+
+/// Format of the lists written by an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The url of each post page, one per line, as `add favorites` and
+    /// `get views` read them.
+    #[default]
+    Text,
+    /// `id,post_url,file_url,md5`, after a header line.
+    Csv,
+    /// One JSON object per line, holding the same fields as CSV.
+    JsonLines,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(Self::Text),
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "jsonlines" => Ok(Self::JsonLines),
+            _ => Err(Error::parse("unknown export format", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        })
+    }
+}
+
+/// What an export tells about a post.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    id: u64,
+    post_url: String,
+    file_url: Option<&'a str>,
+    md5: Option<&'a str>,
+}
+
+impl Session {
+    /// Whether the posts listed by the session are written out rather than
+    /// downloaded, which only getting posts or favorites does.
+    pub(crate) fn exports(&self) -> bool {
+        self.options.export.is_some()
+            && matches!(self.options.action, Action::GetPosts | Action::GetFavorites)
+    }
+
+    /// Whether the posts listed by the session are written to stdout, in
+    /// which case progress goes to stderr.
+    pub(crate) fn exports_to_stdout(&self) -> bool {
+        self.exports() && self.options.output.is_none()
+    }
+
+    /// Write the listed posts which are wanted into the output of the
+    /// session, instead of downloading them.
+    ///
+    /// Posts only known by their id are fetched for their file url and md5,
+    /// unless the session is set to be `quick`.
+    pub(crate) async fn export_listed(
+        &self,
+        listed: impl Stream<Item = Result<Listed>>,
+        destination: Destination<'_>,
+    ) -> Result<()> {
+        let format = self.options.export.unwrap_or_default();
+        {
+            let mut output = self.output.lock().unwrap();
+            if output.is_none() {
+                *output = Some(self.open_output(format)?);
+            }
+        }
+
+        let mut posts = pin!(listed
+            .map_ok(|listed| async move {
+                match listed {
+                    Listed::Post(post) => Ok(*post),
+                    Listed::Id(id) if self.options.quick => Ok(Post {
+                        id: id
+                            .parse()
+                            .map_err(|_| Error::parse("invalid post id", &id))?,
+                        ..Default::default()
+                    }),
+                    Listed::Id(id) => self.get_post(&id).await,
+                }
+            })
+            .try_buffered(self.concurrency()));
+
+        while let Some(post) = posts.try_next().await? {
+            if destination.accepts(&post) {
+                self.write_record(&post, format)?;
+            }
+        }
+
+        let mut output = self.output.lock().unwrap();
+        if let Some(out) = output.as_mut() {
+            out.flush().map_err(|e| Error::io(self.output_name(), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Write given post into the output of the session.
+    fn write_record(&self, post: &Post, format: ExportFormat) -> Result<()> {
+        let file_url = post.file_url.as_deref();
+        let record = Record {
+            id: post.id,
+            post_url: self.backend.post_url(&post.id.to_string()),
+            file_url,
+            md5: post
+                .md5
+                .as_deref()
+                .or_else(|| file_url.and_then(md5_from_url)),
+        };
+
+        let line = match format {
+            ExportFormat::Text => record.post_url,
+            ExportFormat::Csv => [
+                record.id.to_string(),
+                csv_field(&record.post_url),
+                csv_field(record.file_url.unwrap_or_default()),
+                csv_field(record.md5.unwrap_or_default()),
+            ]
+            .join(","),
+            ExportFormat::JsonLines => serde_json::to_string(&record)
+                .map_err(|e| Error::parse(e.to_string(), &post.id.to_string()))?,
+        };
+
+        let mut output = self.output.lock().unwrap();
+        match output.as_mut() {
+            Some(out) => writeln!(out, "{line}").map_err(|e| Error::io(self.output_name(), e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Open the file or stdout the session exports to, writing the header of
+    /// the format if any.
+    fn open_output(&self, format: ExportFormat) -> Result<Box<dyn Write + Send>> {
+        let mut out: Box<dyn Write + Send> = match self.options.output.as_deref() {
+            Some(path) => Box::new(io::BufWriter::new(
+                File::create(path).map_err(|e| Error::io(path, e))?,
+            )),
+            None => Box::new(io::stdout()),
+        };
+        if format == ExportFormat::Csv {
+            writeln!(out, "id,post_url,file_url,md5")
+                .map_err(|e| Error::io(self.output_name(), e))?;
+        }
+
+        Ok(out)
+    }
+
+    fn output_name(&self) -> &str {
+        self.options.output.as_deref().unwrap_or("stdout")
+    }
+}
+
+/// Quote a CSV field if it holds a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
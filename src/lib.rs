@@ -1,15 +1,60 @@
 use std::{
-    borrow::Cow,
-    error::Error,
+    collections::HashSet,
     fs::{self, File},
-    io::{self, Read, Write},
-    ops::{Range, RangeInclusive},
-    path::Path,
+    future::Future,
+    io::{self, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
 };
 
-const PID_STEP_VIEW: u64 = 50;
-const PID_STEP_LIST: u64 = 42;
-const TITLE_LENGTH_LIMIT: usize = 100;
+use futures_util::{future::Either, stream, Stream, StreamExt, TryStreamExt};
+
+mod archive;
+pub mod backend;
+mod error;
+mod export;
+mod part;
+mod post;
+mod rate_limit;
+mod retry;
+mod sanitize;
+mod sync;
+mod tag_list;
+mod template;
+mod verify;
+
+pub use archive::{Archive, ARCHIVE_FILE_NAME};
+pub use backend::{ApiFormat, Backend, Site};
+pub use error::{Error, Result};
+pub use export::ExportFormat;
+pub use post::{Post, Rating};
+pub use retry::RetryPolicy;
+pub use template::Template;
+
+use backend::{md5_from_url, Change, Credentials, Outcome};
+use part::PartFile;
+use rate_limit::RateLimiter;
+use tag_list::{Destination, Rule, Search, TagList};
+
+/// Print a progress message like `println!`, onto stderr instead while the
+/// session exports to stdout.
+macro_rules! progress {
+    ($session:expr, $($arg:tt)*) => {
+        if $session.exports_to_stdout() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Number of posts requested per page from the API.
+const API_LIMIT: u64 = 42;
 
 #[derive(Debug, Default)]
 enum Action {
@@ -17,12 +62,20 @@ enum Action {
     GetPosts,
     GetFavorites,
     AddFavorites,
+    RemoveFavorites,
+    SyncFavorites,
     GetViews,
+    Verify,
 }
 
 #[derive(Debug, Default)]
 pub struct SessionOptions {
     action: Action,
+    // Site
+    site: Site,
+    backend: Option<Box<dyn Backend>>,
+    base_url: Option<String>,
+    cdn_hosts: Vec<String>,
     // Cookies and tokens
     api_key: Option<String>,
     user_id: Option<String>,
@@ -35,9 +88,29 @@ pub struct SessionOptions {
     tags: Option<String>,
     file: Option<String>,
     folder: Option<String>,
+    archive: Option<String>,
+    template: Template,
+    no_split: bool,
+    rules: Vec<Rule>,
     // Methods
     api: bool,
+    api_format: ApiFormat,
     quick: bool,
+    webdriver: bool,
+    repair: bool,
+    dry_run: bool,
+    push: bool,
+    force: bool,
+    concurrency: Option<usize>,
+    retry: RetryPolicy,
+    page_rate: Option<f64>,
+    file_rate: Option<f64>,
+    // Export
+    export: Option<ExportFormat>,
+    output: Option<String>,
+    // Sidecar files
+    metadata: bool,
+    tag_list: bool,
 }
 
 impl SessionOptions {
@@ -57,10 +130,44 @@ impl SessionOptions {
         self.action = Action::AddFavorites;
         self
     }
+    pub fn remove_favorites(&mut self) -> &mut Self {
+        self.action = Action::RemoveFavorites;
+        self
+    }
+    /// Make the folder and the favorites of current user match.
+    pub fn sync_favorites(&mut self) -> &mut Self {
+        self.action = Action::SyncFavorites;
+        self
+    }
     pub fn get_views(&mut self) -> &mut Self {
         self.action = Action::GetViews;
         self
     }
+    /// Check the files in the folder against the md5 of their posts.
+    pub fn verify(&mut self) -> &mut Self {
+        self.action = Action::Verify;
+        self
+    }
+
+    pub fn site(&mut self, site: Site) -> &mut Self {
+        self.site = site;
+        self
+    }
+    /// Talk to a mirror or a self-hosted instance of the site at given url.
+    pub fn base_url(&mut self, s: &str) -> &mut Self {
+        self.base_url = Some(s.to_owned());
+        self
+    }
+    /// Also accept files served from given host, e.g. `cdn.example.com`.
+    pub fn cdn_host(&mut self, s: &str) -> &mut Self {
+        self.cdn_hosts.push(s.to_owned());
+        self
+    }
+    /// Use a custom backend instead of the one of a built-in site.
+    pub fn backend(&mut self, backend: impl Backend + 'static) -> &mut Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
 
     pub fn user_id(&mut self, s: &str) -> &mut Self {
         self.user_id = Some(s.to_owned());
@@ -100,15 +207,122 @@ impl SessionOptions {
         self.folder = Some(s.to_owned());
         self
     }
+    /// Path of the download archive. Defaults to `.getbooru-archive` in the
+    /// download folder.
+    pub fn archive(&mut self, s: &str) -> &mut Self {
+        self.archive = Some(s.to_owned());
+        self
+    }
 
     pub fn api(&mut self, b: bool) -> &mut Self {
         self.api = b;
         self
     }
+    /// Format of API responses, on sites offering a choice. Defaults to JSON.
+    pub fn api_format(&mut self, format: ApiFormat) -> &mut Self {
+        self.api_format = format;
+        self
+    }
     pub fn quick(&mut self, b: bool) -> &mut Self {
         self.quick = b;
         self
     }
+    /// Read favorites through a browser driven by a WebDriver listening on
+    /// `localhost:4444`, instead of plain requests.
+    pub fn webdriver(&mut self, b: bool) -> &mut Self {
+        self.webdriver = b;
+        self
+    }
+    /// List the posts found by the session in given format instead of
+    /// downloading them.
+    pub fn export(&mut self, format: ExportFormat) -> &mut Self {
+        self.export = Some(format);
+        self
+    }
+    /// Write exports into the file at `path` rather than to stdout.
+    pub fn output(&mut self, path: &str) -> &mut Self {
+        self.output = Some(path.to_owned());
+        self
+    }
+    /// Write post metadata into a `.json` file next to each download.
+    pub fn metadata(&mut self, b: bool) -> &mut Self {
+        self.metadata = b;
+        self
+    }
+    /// Write post tags, one per line, into a `.txt` file next to each download.
+    pub fn tag_list(&mut self, b: bool) -> &mut Self {
+        self.tag_list = b;
+        self
+    }
+    /// Save files at the path given by `template`, relative to the folder.
+    /// Defaults to `{id} {title}.{ext}`.
+    pub fn template(&mut self, template: Template) -> &mut Self {
+        self.template = template;
+        self
+    }
+    /// Save the results of each line of the tag list into a subfolder named
+    /// after its tags, unless the line tells another one. Enabled by default.
+    pub fn split(&mut self, b: bool) -> &mut Self {
+        self.no_split = !b;
+        self
+    }
+    /// Save posts tagged `tag` into the subfolder `folder`, whichever search
+    /// finds them. Rules are checked in the order they are added.
+    pub fn rule(&mut self, tag: &str, folder: &str) -> &mut Self {
+        self.rules.push(Rule {
+            tag: tag.to_owned(),
+            folder: folder.to_owned(),
+        });
+        self
+    }
+    /// Download broken files again when verifying a folder.
+    pub fn repair(&mut self, b: bool) -> &mut Self {
+        self.repair = b;
+        self
+    }
+    /// Only list the favorites which would be added or removed, or when
+    /// syncing them, only print the difference.
+    pub fn dry_run(&mut self, b: bool) -> &mut Self {
+        self.dry_run = b;
+        self
+    }
+    /// When syncing favorites, change them to match the folder instead of
+    /// downloading the missing ones.
+    pub fn push(&mut self, b: bool) -> &mut Self {
+        self.push = b;
+        self
+    }
+    /// Let syncing favorites remove the ones missing from the folder.
+    pub fn force(&mut self, b: bool) -> &mut Self {
+        self.force = b;
+        self
+    }
+    /// Download up to `n` posts at the same time. Defaults to 1.
+    pub fn concurrency(&mut self, n: usize) -> &mut Self {
+        self.concurrency = Some(n.max(1));
+        self
+    }
+    /// Retry failed requests as told by `policy`.
+    pub fn retry(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry = policy;
+        self
+    }
+    /// Send at most `rate` requests per second for pages and API listings.
+    /// Unlimited by default.
+    pub fn page_rate(&mut self, rate: f64) -> &mut Self {
+        self.page_rate = Some(rate);
+        self
+    }
+    /// Start at most `rate` file downloads per second. Unlimited by default.
+    pub fn file_rate(&mut self, rate: f64) -> &mut Self {
+        self.file_rate = Some(rate);
+        self
+    }
+    /// Send each request up to `n` times in total. Defaults to 3.
+    pub fn max_attempts(&mut self, n: u32) -> &mut Self {
+        self.retry.max_attempts = n.max(1);
+        self
+    }
 
     pub fn create(self) -> Session {
         Session::create(self)
@@ -117,6 +331,31 @@ impl SessionOptions {
 
 pub struct Session {
     options: SessionOptions,
+    backend: Box<dyn Backend>,
+    archive: Archive,
+    client: OnceLock<reqwest::Client>,
+    /// Whether the API refused a request, and searches now scrape the site.
+    api_refused: AtomicBool,
+    page_limiter: RateLimiter,
+    file_limiter: RateLimiter,
+    /// Where exports are written, opened on first use.
+    output: Mutex<Option<Box<dyn Write + Send>>>,
+}
+
+/// A post found while listing a search, in full through the API or only by
+/// its id when scraped.
+enum Listed {
+    Post(Box<Post>),
+    Id(String),
+}
+
+impl Listed {
+    fn id(&self) -> String {
+        match self {
+            Self::Post(post) => post.id.to_string(),
+            Self::Id(id) => id.clone(),
+        }
+    }
 }
 
 impl Session {
@@ -124,77 +363,292 @@ impl Session {
         SessionOptions::new()
     }
 
-    fn create(options: SessionOptions) -> Self {
-        Self { options }
+    fn create(mut options: SessionOptions) -> Self {
+        let backend = options.backend.take().unwrap_or_else(|| {
+            options.site.backend(
+                options.base_url.as_deref(),
+                &options.cdn_hosts,
+                Credentials {
+                    api_key: options.api_key.clone(),
+                    user_id: options.user_id.clone(),
+                    pass_hash: options.pass_hash.clone(),
+                },
+            )
+        });
+        let site = backend::host(backend.base_url());
+        let archive = match options.archive.as_deref() {
+            Some(path) => Archive::new(path, site),
+            None => Archive::new(
+                Path::new(options.folder.as_deref().unwrap_or(".")).join(ARCHIVE_FILE_NAME),
+                site,
+            ),
+        };
+
+        Self {
+            backend,
+            archive,
+            client: OnceLock::new(),
+            api_refused: AtomicBool::new(false),
+            page_limiter: RateLimiter::new(options.page_rate),
+            file_limiter: RateLimiter::new(options.file_rate),
+            output: Mutex::new(None),
+            options,
+        }
+    }
+
+    fn extract_id_from_url<'a>(&self, url: &'a str) -> Result<&'a str> {
+        self.backend
+            .extract_id(url)
+            .ok_or_else(|| Error::not_found("post id", url))
     }
 
-    fn extract_file_url(res: &str) -> Result<&str, &'static str> {
-        let re = regex::Regex::new(r"https://(img[123]|video-cdn[123]).gelbooru.com/(.*)\.[A-z0-9]+").unwrap();
-        let mat = re.find(res).ok_or("cannot find file url")?;
+    /// Extract post title from given response of the post page at `src`, as
+    /// is. Templates make it valid in file names when rendering them.
+    fn extract_title<'a>(res: &'a str, src: &str) -> Result<&'a str> {
+        let re = regex::Regex::new("<title>(.*?)</title>").unwrap();
+        let cap = re
+            .captures(res)
+            .ok_or_else(|| Error::not_found("title", src))?;
+        let mat = cap.get(1).ok_or_else(|| Error::not_found("title", src))?;
 
-        Ok(&res[mat.range()])
+        Ok(mat.as_str())
     }
 
-    fn extract_id_from_url(url: &str) -> Result<&str, &'static str> {
-        let re = regex::Regex::new("id=([0-9]+)&*").unwrap();
-        let cap = re.captures(url).ok_or("cannot find id")?;
-        let mat = cap.get(1).ok_or("cannot get id")?;
+    /// Write enabled sidecar files for the download at `path`.
+    fn save_sidecars(&self, path: &str, post: &Post) -> Result<()> {
+        if self.options.metadata {
+            let json =
+                serde_json::to_vec_pretty(post).map_err(|e| Error::parse(e.to_string(), path))?;
+            Self::save_file(&format!("{path}.json"), &json)?;
+        }
+        if self.options.tag_list {
+            let mut list = post.tags.join("\n");
+            list.push('\n');
+            Self::save_file(&format!("{path}.txt"), list.as_bytes())?;
+        }
 
-        Ok(&url[mat.range()])
+        Ok(())
     }
 
-    /// Extract post title from given response,
-    /// replacing all invalid characters across different platforms with `_`.
-    fn extract_title(res: &str) -> Result<Cow<str>, &'static str> {
-        let re = regex::Regex::new("<title>(.*?)</title>").unwrap();
-        let cap = re.captures(res).ok_or("cannot find title")?;
-        let mat = cap.get(1).ok_or("cannot get title")?;
-        let range = if mat.range().len() < TITLE_LENGTH_LIMIT {
-            mat.range()
-        } else {
-            Range {
-                start: mat.start(),
-                end: mat.start() + TITLE_LENGTH_LIMIT,
-            }
-        };
-        let re = regex::Regex::new("[/\\?%*:|\"<>]").unwrap();
+    fn wants_sidecars(&self) -> bool {
+        self.options.metadata || self.options.tag_list
+    }
 
-        Ok(re.replace_all(&res[range], "_"))
+    /// Read the whole content of a local file.
+    fn read_file(path: &str) -> Result<String> {
+        fs::read_to_string(path).map_err(|e| Error::io(path, e))
     }
 
-    async fn new_client_webdriver(
-        &self,
-    ) -> Result<fantoccini::Client, fantoccini::error::CmdError> {
-        let c = fantoccini::ClientBuilder::native()
-            .connect("http://localhost:4444")
-            .await
-            .expect("a WebDriver should be running on port 4444");
+    /// Get the HTTP client of the session, carrying its cookies.
+    fn client(&self) -> Result<&reqwest::Client> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
 
-        print!("Entering home page...");
-        io::stdout().flush().expect("cannot flush stdout");
+        let client = self.new_client_http()?;
+        Ok(self.client.get_or_init(|| client))
+    }
 
-        c.goto("https://gelbooru.com/index.php").await?;
+    /// Send a GET request to `url` and read the response body with `read`,
+    /// turning non-success status codes into errors.
+    ///
+    /// Each attempt waits for `limiter` first. Requests which fail for a
+    /// transient reason are sent again as told by the retry policy of the
+    /// session.
+    async fn fetch<T, F, Fut>(&self, url: &str, limiter: &RateLimiter, read: F) -> Result<T>
+    where
+        F: Fn(reqwest::Response) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.fetch_with(reqwest::Method::GET, url, limiter, |req| req, read)
+            .await
+    }
 
-        println!("adding cookies...");
+    /// Same as [`fetch`](Self::fetch), sending a `method` request and
+    /// letting `prepare` change it before each attempt.
+    async fn fetch_with<T, P, F, Fut>(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        limiter: &RateLimiter,
+        prepare: P,
+        read: F,
+    ) -> Result<T>
+    where
+        P: Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+        F: Fn(reqwest::Response) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let policy = &self.options.retry;
+        let mut attempt = 1;
+        loop {
+            let retry = attempt < policy.max_attempts;
+            limiter.acquire().await;
+            let delay = match prepare(self.client()?.request(method.clone(), url))
+                .send()
+                .await
+            {
+                Ok(res) if res.status().is_success() => match read(res).await {
+                    Ok(body) => return Ok(body),
+                    Err(e) if retry && RetryPolicy::is_transient_error(&e) => {
+                        eprintln!("{e}");
+                        policy.delay(attempt)
+                    }
+                    Err(e) => return Err(e),
+                },
+                Ok(res) if retry && policy.is_transient(res.status()) => {
+                    eprintln!("{url} returned {}", res.status());
+                    match policy.retry_after(&res) {
+                        // Waiting that long is left to a later run.
+                        Some(delay) if delay > policy.max_backoff => {
+                            return Err(Error::RateLimited {
+                                url: url.to_owned(),
+                            });
+                        }
+                        Some(delay) => delay,
+                        None => policy.delay(attempt),
+                    }
+                }
+                Ok(res) => return Err(Error::from_status(url, res.status())),
+                Err(e) => {
+                    let e = Error::http(url, e);
+                    if !retry || !RetryPolicy::is_transient_error(&e) {
+                        return Err(e);
+                    }
+                    eprintln!("{e}");
+                    policy.delay(attempt)
+                }
+            };
 
-        if let Some(user_id) = self.options.user_id.as_deref() {
-            let cookie = fantoccini::cookies::Cookie::new("user_id", user_id.to_owned());
-            c.add_cookie(cookie).await?;
-        }
-        if let Some(pass_hash) = self.options.pass_hash.as_deref() {
-            let cookie = fantoccini::cookies::Cookie::new("pass_hash", pass_hash.to_owned());
-            c.add_cookie(cookie).await?;
+            attempt += 1;
+            progress!(
+                self,
+                "Retrying in {:.1}s ({attempt}/{})...",
+                delay.as_secs_f32(),
+                policy.max_attempts
+            );
+            tokio::time::sleep(delay).await;
         }
-        if let Some(fringe_benefits) = self.options.fringe_benefits.as_deref() {
-            let cookie =
-                fantoccini::cookies::Cookie::new("fringeBenefits", fringe_benefits.to_owned());
-            c.add_cookie(cookie).await?;
+    }
+
+    /// Send a GET request to `url` and return the response body as text.
+    async fn get_text(&self, url: &str) -> Result<String> {
+        self.fetch(url, &self.page_limiter, |res| async move {
+            res.text().await.map_err(|e| Error::http(url, e))
+        })
+        .await
+    }
+
+    /// Write `bytes` into a newly created file at `path`.
+    fn save_file(path: &str, bytes: &[u8]) -> Result<()> {
+        File::create(path)
+            .and_then(|mut f| f.write_all(bytes))
+            .map_err(|e| Error::io(path, e))
+    }
+
+    /// Stream the file at `url` into `path`, through a `.part` file renamed
+    /// into place once the whole body has arrived.
+    ///
+    /// A `.part` file left by an interrupted download is resumed where it
+    /// stopped, if the server supports it and the file has not changed.
+    ///
+    /// The file is hashed as it arrives and checked against `md5`, if known.
+    /// Returns its md5.
+    async fn download_file(&self, url: &str, path: &str, md5: Option<&str>) -> Result<String> {
+        let part = PartFile::new(path);
+        self.fetch_with(
+            reqwest::Method::GET,
+            url,
+            &self.file_limiter,
+            |req| part.prepare(req),
+            |mut res| {
+                let part = &part;
+                async move {
+                    let (mut file, offset) = part.open(&res, url)?;
+                    if offset > 0 {
+                        println!("Resuming {url} from {offset} bytes...");
+                    }
+
+                    let mut context = md5::Context::new();
+                    io::copy(&mut file, &mut context).map_err(|e| Error::io(path, e))?;
+
+                    let expected = res.content_length().map(|len| offset + len);
+                    let mut received = offset;
+                    while let Some(chunk) = res.chunk().await.map_err(|e| Error::http(url, e))? {
+                        file.write_all(&chunk).map_err(|e| Error::io(path, e))?;
+                        context.consume(&chunk);
+                        received += chunk.len() as u64;
+                    }
+                    file.sync_all().map_err(|e| Error::io(path, e))?;
+
+                    if let Some(expected) = expected.filter(|e| *e != received) {
+                        return Err(Error::Incomplete {
+                            url: url.to_owned(),
+                            expected,
+                            received,
+                        });
+                    }
+
+                    let actual = format!("{:x}", context.compute());
+                    match md5 {
+                        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+                            part.discard()?;
+                            Err(Error::Checksum {
+                                url: url.to_owned(),
+                                expected: expected.to_owned(),
+                                actual,
+                            })
+                        }
+                        _ => Ok(actual),
+                    }
+                }
+            },
+        )
+        .await
+        .and_then(|md5| {
+            part.finish()?;
+            Ok(md5)
+        })
+    }
+
+    fn concurrency(&self) -> usize {
+        self.options.concurrency.unwrap_or(1)
+    }
+
+    async fn new_client_webdriver(&self) -> Result<fantoccini::Client> {
+        const WEBDRIVER: &str = "http://localhost:4444";
+        let home = self.backend.base_url();
+
+        let c = fantoccini::ClientBuilder::native()
+            .connect(WEBDRIVER)
+            .await
+            .map_err(|e| Error::webdriver(WEBDRIVER, e))?;
+
+        progress!(self, "Entering home page...");
+
+        self.page_limiter.acquire().await;
+        c.goto(home).await.map_err(|e| Error::webdriver(home, e))?;
+
+        progress!(self, "Adding cookies...");
+
+        let cookies = [
+            ("user_id", self.options.user_id.as_deref()),
+            ("pass_hash", self.options.pass_hash.as_deref()),
+            ("fringeBenefits", self.options.fringe_benefits.as_deref()),
+        ];
+        for (name, value) in cookies {
+            if let Some(value) = value {
+                let cookie = fantoccini::cookies::Cookie::new(name, value.to_owned());
+                c.add_cookie(cookie)
+                    .await
+                    .map_err(|e| Error::webdriver(home, e))?;
+            }
         }
 
         Ok(c)
     }
 
-    fn new_client_http(&self) -> Result<reqwest::Client, reqwest::Error> {
+    fn new_client_http(&self) -> Result<reqwest::Client> {
         let cookie = format!(
             "user_id={}; pass_hash={}; fringeBenefits={}",
             self.options.user_id.as_deref().unwrap_or_default(),
@@ -205,432 +659,718 @@ impl Session {
         headers.insert(
             reqwest::header::COOKIE,
             reqwest::header::HeaderValue::from_str(&cookie)
-                .expect("invalid characters found when generating Cookie header"),
+                .map_err(|_| Error::auth("invalid characters found in cookies"))?,
         );
 
-        reqwest::Client::builder().default_headers(headers).build()
+        reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|e| Error::http("", e))
     }
 
-    async fn add_to_favorites(&self) -> Result<(), Box<dyn Error>> {
-        println!("Start adding urls to favorites...");
+    async fn add_to_favorites(&self) -> Result<()> {
+        self.change_favorites(true).await
+    }
 
-        let client = self.new_client_webdriver().await?;
+    async fn remove_from_favorites(&self) -> Result<()> {
+        self.change_favorites(false).await
+    }
+
+    /// Add the posts listed in the file of the session to the favorites of
+    /// current user, or remove them from it, one post per line.
+    ///
+    /// If the session is a dry run, posts are only listed.
+    async fn change_favorites(&self, add: bool) -> Result<()> {
+        let (verb, _, _) = Self::favorites_words(add);
+        println!("Start {verb} favorites...");
+
+        if !self.backend.can_change_favorites() {
+            return Err(Error::unsupported(
+                format!("{verb} favorites"),
+                self.backend.base_url(),
+            ));
+        }
 
-        let mut buf = String::new();
-        if let Some(f) = &self.options.file {
-            File::open(f)?.read_to_string(&mut buf)?;
+        let buf = match &self.options.file {
+            Some(f) => Self::read_file(f)?,
+            None => String::new(),
         };
-        for url in buf.lines() {
-            print!("Entering {} ...", url);
-            io::stdout().flush().expect("cannot flush stdout");
+        let lines = buf.lines().map(str::trim).filter(|l| !l.is_empty());
 
-            client.goto(url).await?;
+        // The site cannot always tell whether a post is in favorites, and a
+        // dry run sends nothing to ask it.
+        let favorites: Option<HashSet<String>> = match !add || self.options.dry_run {
+            true => Some(self.favorites()?.map_ok(|l| l.id()).try_collect().await?),
+            false => None,
+        };
+        let (changed, kept, err_count) = self.change_all(lines, add, favorites.as_ref()).await;
 
-            match client
-                .find(fantoccini::Locator::Css("h4#scrollebox a:nth-child(3)"))
-                .await
-            {
-                Ok(t) if t.text().await? == "Favorite" => {
-                    println!("adding to favorites.");
-                    t.click().await?;
+        let (changed_label, kept_label) = match (add, self.options.dry_run) {
+            (true, false) => ("Added", "already present"),
+            (false, false) => ("Removed", "not present"),
+            (true, true) => ("To add", "already present"),
+            (false, true) => ("To remove", "not present"),
+        };
+        println!(
+            "Finished {verb} favorites. {changed_label}: {changed}, {kept_label}: {kept}, error count: {err_count}"
+        );
+
+        Ok(())
+    }
+
+    /// Add the posts given by `lines`, i.e. their urls, their ids or the md5
+    /// of their files, to the favorites of current user, or remove them from
+    /// it, telling what happened to each one.
+    ///
+    /// Posts which `favorites`, if given, tells need no change are skipped.
+    /// Returns the number of posts changed, left unchanged and failed. If
+    /// the session is a dry run, posts are only listed.
+    async fn change_all<'a>(
+        &self,
+        lines: impl Iterator<Item = &'a str>,
+        add: bool,
+        favorites: Option<&HashSet<String>>,
+    ) -> (usize, usize, usize) {
+        let (verb, done, unchanged) = Self::favorites_words(add);
+        let mut results = stream::iter(lines)
+            .map(|line| async move {
+                let result = match self.resolve_id(line).await {
+                    Ok(id) if favorites.is_some_and(|f| f.contains(&id) == add) => {
+                        Ok((id, Some(Outcome::Unchanged)))
+                    }
+                    Ok(id) if self.options.dry_run => Ok((id, None)),
+                    Ok(id) => self.change_favorite(&id, add).await.map(|o| (id, Some(o))),
+                    Err(e) => Err(e),
+                };
+                (line, result)
+            })
+            .buffered(self.concurrency());
+        let (mut changed, mut kept, mut err_count) = (0, 0, 0);
+        while let Some((line, result)) = results.next().await {
+            match result {
+                Ok((id, None)) if id == line => {
+                    println!("{line}: would be {done} favorites.");
+                    changed += 1;
+                }
+                Ok((id, None)) => {
+                    println!("{line}: {id} would be {done} favorites.");
+                    changed += 1;
+                }
+                Ok((_, Some(Outcome::Done))) => {
+                    println!("{line}: {done} favorites.");
+                    changed += 1;
                 }
-                Ok(_) => {
-                    println!("already in favorites, skipping.");
+                Ok((_, Some(Outcome::Unchanged))) => {
+                    println!("{line}: {unchanged} favorites, skipping.");
+                    kept += 1;
                 }
                 Err(e) => {
-                    println!("problem finding favorite button, skipping: {e}.");
+                    eprintln!("{line}: failed {verb} favorites: {e}");
+                    err_count += 1;
                 }
+            }
+        }
+
+        (changed, kept, err_count)
+    }
+
+    /// How to tell what changing favorites does.
+    fn favorites_words(add: bool) -> (&'static str, &'static str, &'static str) {
+        match add {
+            true => ("adding to", "added to", "already in"),
+            false => ("removing from", "removed from", "not in"),
+        }
+    }
+
+    /// Add the post with given id to the favorites of current user, or
+    /// remove it from them.
+    async fn change_favorite(&self, id: &str, add: bool) -> Result<Outcome> {
+        let change = match add {
+            true => self.backend.add_favorite(id)?,
+            false => self.backend.remove_favorite(id)?,
+        };
+        let res = self.send_change(&change).await;
+
+        match add {
+            true => self.backend.parse_add_favorite(res, &change.url),
+            false => self.backend.parse_remove_favorite(res, &change.url),
+        }
+    }
+
+    /// Get the id of the post given by its url, its id or the md5 of its
+    /// file, searching for the latter.
+    async fn resolve_id(&self, s: &str) -> Result<String> {
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(s.to_owned());
+        }
+        if s.len() == 32 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let search = Search {
+                start: Some(1),
+                end: Some(1),
+                limit: Some(1),
+                ..Search::new(&format!("md5:{s}"))
             };
+            let listed = pin!(self.list(&search)).try_next().await?;
+            return listed
+                .map(|listed| listed.id())
+                .ok_or_else(|| Error::not_found("post with md5", s));
         }
 
-        println!("Finished adding to favorites.");
+        self.extract_id_from_url(s).map(str::to_owned)
+    }
+
+    /// Send given change to the site, returning the response body.
+    async fn send_change(&self, change: &Change) -> Result<String> {
+        let url = change.url.as_str();
+        self.fetch_with(
+            change.method.clone(),
+            url,
+            &self.page_limiter,
+            |req| req,
+            |res| async move { res.text().await.map_err(|e| Error::http(url, e)) },
+        )
+        .await
+    }
+
+    async fn get_favorites(&self) -> Result<()> {
+        progress!(self, "Start getting favorites...");
+
+        if self.options.webdriver {
+            self.get_favorites_webdriver().await?;
+        } else {
+            self.download_listed(self.favorites()?, self.destination())
+                .await?;
+        }
 
-        client.close().await?;
+        progress!(self, "Finished getting favorites.");
 
         Ok(())
     }
 
-    async fn get_favorites(&self) -> Result<(), Box<dyn Error>> {
-        println!("Start getting favorites...");
+    /// List the favorites of current user, searching them by tags on sites
+    /// which can.
+    fn favorites(&self) -> Result<impl Stream<Item = Result<Listed>> + '_> {
+        self.check_user_id()?;
+
+        if let Some(tags) = self.backend.favorites_tags() {
+            Ok(Either::Left(self.list(&Search::new(&tags))))
+        } else if self.backend.favorites_url(1).is_some()
+            || self.backend.api_favorites_url(1).is_some()
+        {
+            Ok(Either::Right(self.list_favorites()))
+        } else {
+            Err(Error::unsupported("favorites", self.backend.base_url()))
+        }
+    }
 
-        let client = self.new_client_webdriver().await?;
+    /// Favorites are the ones of the user `user_id` tells.
+    fn check_user_id(&self) -> Result<()> {
+        match self.options.user_id {
+            Some(_) => Ok(()),
+            None => Err(Error::auth("user_id is needed to get favorites")),
+        }
+    }
 
-        let base = format!(
-            "https://gelbooru.com/index.php?page=favorites&s=view&id={}",
-            self.options.user_id.as_deref().unwrap_or_default()
-        );
+    /// List the favorites of current user page by page, on sites which
+    /// cannot search them by tags.
+    ///
+    /// Pages come from the API if the session is set to use it and the site
+    /// has one for favorites, from the favorites pages otherwise. Should the
+    /// API fail, the listing goes on from the same page without it.
+    fn list_favorites(&self) -> impl Stream<Item = Result<Listed>> + '_ {
+        let start = self.options.start.unwrap_or(1);
+        let end = self.options.end.unwrap_or(u64::MAX);
+
+        stream::try_unfold(Some(start), move |page| async move {
+            let Some(page) = page.filter(|p| *p <= end) else {
+                return Ok(None);
+            };
+            progress!(self, "Entering favorites, page {}...", page);
+
+            let api = match self.backend.api_favorites_url(page) {
+                Some(_) if self.backend.favorites_url(page).is_none() => true,
+                Some(_) => self.options.api && !self.api_refused.load(Ordering::Relaxed),
+                None => false,
+            };
+            let ids = match self.list_favorites_page(page, api).await {
+                Err(e) if api && self.backend.favorites_url(page).is_some() => {
+                    progress!(self, "The API failed listing favorites: {e}");
+                    progress!(self, "Going on without the API from page {page}...");
+                    self.api_refused.store(true, Ordering::Relaxed);
+                    self.list_favorites_page(page, false).await?
+                }
+                res => res?,
+            };
+            if ids.is_empty() {
+                progress!(self, "no elements present.");
+                return Ok(None);
+            }
+
+            let listed = ids.into_iter().map(|id| Ok(Listed::Id(id)));
+            Ok(Some((stream::iter(listed), Some(page + 1))))
+        })
+        .try_flatten()
+    }
+
+    /// List the ids of the favorites on given page, through the API or from
+    /// the favorites pages.
+    async fn list_favorites_page(&self, page: u64, api: bool) -> Result<Vec<String>> {
+        let url = match api {
+            true => self.backend.api_favorites_url(page),
+            false => self.backend.favorites_url(page),
+        }
+        .unwrap_or_default();
+        let res = self.get_text(&url).await?;
+
+        if api {
+            self.backend.parse_api_favorites(&res, &url)
+        } else {
+            self.backend.parse_favorites(&res, &url)
+        }
+    }
+
+    /// Get favorites by reading their pages in a browser driven through
+    /// WebDriver.
+    async fn get_favorites_webdriver(&self) -> Result<()> {
+        self.check_user_id()?;
+        if self.backend.favorites_url(1).is_none() {
+            return Err(Error::unsupported("favorites", self.backend.base_url()));
+        }
+
+        let client = self.new_client_webdriver().await?;
 
         let range = RangeInclusive::new(
             self.options.start.unwrap_or(1),
             self.options.end.unwrap_or(u64::MAX),
         );
         for page in range {
-            print!("Entering favorites, page {}...", page);
-            io::stdout().flush().expect("cannot flush stdout");
-
-            let pid = (page - 1) * PID_STEP_VIEW;
-            let url = format!("{}&pid={}", base, pid);
-            client.goto(&url).await?;
+            progress!(self, "Entering favorites, page {}...", page);
 
-            println!("getting elements...");
+            let url = self.backend.favorites_url(page).unwrap_or_default();
+            self.page_limiter.acquire().await;
+            client
+                .goto(&url)
+                .await
+                .map_err(|e| Error::webdriver(&url, e))?;
 
             let a_s = client
                 .find_all(fantoccini::Locator::Css("span.thumb a:first-child"))
-                .await?;
+                .await
+                .map_err(|e| Error::webdriver(&url, e))?;
             if a_s.is_empty() {
-                println!("No elements present.");
+                progress!(self, "no elements present.");
                 break;
             }
 
-            self.get_elements_webdriver(a_s).await?;
+            let ids = self.get_elements_webdriver(a_s, &url).await?;
+            let listed = ids.into_iter().map(|id| Ok(Listed::Id(id)));
+            self.download_listed(stream::iter(listed), self.destination())
+                .await?;
         }
 
-        println!("Finished getting favorites.");
-
-        client.close().await?;
+        client
+            .close()
+            .await
+            .map_err(|e| Error::webdriver("close", e))?;
 
         Ok(())
     }
 
+    /// Get the ids of the posts linked by given thumbnails.
     async fn get_elements_webdriver(
         &self,
         a_s: Vec<fantoccini::elements::Element>,
-    ) -> Result<(), Box<dyn Error>> {
-        'outer: for a in a_s {
-            print!("Extracting information...");
-            io::stdout().flush().expect("cannot flush stdout");
-
+        page_url: &str,
+    ) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for a in a_s {
             let src = a
                 .attr("href")
-                .await?
-                .ok_or("cannot find href in thumbnail element")?;
-            let id = Self::extract_id_from_url(&src)?;
-
-            let saved = fs::read_dir(self.options.folder.as_deref().unwrap_or("."))?;
-            for file in saved {
-                let name = file?.file_name();
-                if name
-                    .to_str()
-                    .ok_or("invalid character in saved file names")?
-                    .starts_with(id)
-                {
-                    println!("{id} already exists, skipping.");
-                    continue 'outer;
-                }
-            }
-
-            let client = self.new_client_http()?;
-            let src = format!("https://gelbooru.com/index.php?page=post&s=view&id={id}");
-            self.download(&client, &src).await?;
+                .await
+                .map_err(|e| Error::webdriver(page_url, e))?
+                .ok_or_else(|| Error::not_found("href in thumbnail element", page_url))?;
+            ids.push(self.extract_id_from_url(&src)?.to_owned());
         }
 
-        Ok(())
+        Ok(ids)
     }
 
-    async fn get_posts(&self) -> Result<(), Box<dyn Error>> {
-        println!("Start getting posts...");
+    async fn get_posts(&self) -> Result<()> {
+        progress!(self, "Start getting posts...");
 
-        let client = self.new_client_http()?;
+        self.get_posts_with_tags().await?;
 
-        let base = if self.options.api {
-            format!(
-                "https://gelbooru.com/index.php?page=dapi&s=post&q=index&api_key={}&user_id={}",
-                self.options
-                    .api_key
-                    .as_deref()
-                    .ok_or("api_key is not specified")?,
-                self.options
-                    .user_id
-                    .as_deref()
-                    .ok_or("user_id is not specified")?,
-            )
-        } else {
-            String::from("https://gelbooru.com/index.php?page=post&s=list")
-        };
+        progress!(self, "Finished getting all tags.");
 
-        self.get_posts_with_tags(&base, &client).await?;
+        Ok(())
+    }
 
-        println!("Finished getting all tags.");
+    async fn get_posts_with_tags(&self) -> Result<()> {
+        if let Some(f) = self.options.file.as_deref() {
+            let list = TagList::load(f)?;
+            let rules: Vec<_> = self
+                .options
+                .rules
+                .iter()
+                .chain(&list.rules)
+                .cloned()
+                .collect();
+
+            for search in &list.searches {
+                let split = (!self.options.no_split).then(|| sanitize::sanitize(&search.tags));
+                let destination = Destination {
+                    subfolder: search.destination.as_deref().or(split.as_deref()),
+                    rules: &rules,
+                    ratings: &search.ratings,
+                };
+                let search = Search {
+                    tags: match self.options.tags.as_deref() {
+                        Some(tags) if !tags.is_empty() => format!("{}+{}", search.tags, tags),
+                        _ => search.tags.clone(),
+                    },
+                    ..search.clone()
+                };
+                progress!(self, "Current tags: {}", search.tags);
+
+                self.get_posts_by_tag(&search, destination).await?;
+            }
+        } else {
+            let search = Search::new(self.options.tags.as_deref().unwrap_or_default());
+            self.get_posts_by_tag(&search, self.destination()).await?;
+        }
 
         Ok(())
     }
 
-    async fn get_posts_with_tags(
-        &self,
-        base: &str,
-        client: &reqwest::Client,
-    ) -> Result<(), Box<dyn Error>> {
-        if let Some(f) = self.options.file.as_deref() {
-            let mut buf = String::new();
-            File::open(f)?.read_to_string(&mut buf)?;
+    /// Destination of posts which are not found through a tag list.
+    fn destination(&self) -> Destination<'_> {
+        Destination {
+            subfolder: None,
+            rules: &self.options.rules,
+            ratings: &[],
+        }
+    }
 
-            for current_tag in buf.lines() {
-                if current_tag.starts_with('#') {
-                    continue;
-                }
+    /// Whether posts are listed through the API, either because it is asked
+    /// to or because the site cannot be scraped otherwise.
+    fn uses_api(&self, search: &Search) -> bool {
+        (search.api.unwrap_or(self.options.api) && !self.api_refused.load(Ordering::Relaxed))
+            || self.uses_api_only()
+    }
+
+    /// Whether the site can only be listed through the API.
+    fn uses_api_only(&self) -> bool {
+        self.backend.html_list_url("", 1).is_none()
+    }
+
+    /// Get posts found by given search.
+    async fn get_posts_by_tag(&self, search: &Search, destination: Destination<'_>) -> Result<()> {
+        self.download_listed(self.list(search), destination).await
+    }
 
-                println!(
-                    "Current tags: {}+{}",
-                    current_tag,
-                    self.options.tags.as_deref().unwrap_or_default(),
-                );
+    /// Download the listed posts which are wanted and not in the archive,
+    /// or export them if the session is set to.
+    async fn download_listed(
+        &self,
+        listed: impl Stream<Item = Result<Listed>>,
+        destination: Destination<'_>,
+    ) -> Result<()> {
+        if self.exports() {
+            return self.export_listed(listed, destination).await;
+        }
 
-                if self.options.api {
-                    self.get_posts_api(base, current_tag).await?;
+        listed
+            .try_filter_map(|listed| async move {
+                let id = listed.id();
+                if let Listed::Post(post) = &listed {
+                    if !destination.accepts(post) {
+                        println!("{id} is not of the wanted ratings, skipping.");
+                        return Ok(None);
+                    }
+                    // Restricted or banned posts come without their file.
+                    if post.file_url.is_none() {
+                        println!("{id} has no file url, skipping.");
+                        return Ok(None);
+                    }
+                }
+                if self.archive.contains(&id)? {
+                    println!("{id} already exists, skipping.");
+                    Ok(None)
                 } else {
-                    self.get_posts_noapi(base, current_tag, client).await?;
+                    Ok(Some(listed))
                 }
-            }
-        } else {
-            if self.options.api {
-                self.get_posts_api(base, self.options.tags.as_deref().unwrap_or_default())
-                    .await?;
-            } else {
-                self.get_posts_noapi(
-                    base,
-                    self.options.tags.as_deref().unwrap_or_default(),
-                    client,
-                )
-                .await?;
-            }
-        }
+            })
+            .map(|listed| async move {
+                match listed? {
+                    Listed::Post(post) => self.download_into(&post, destination).await,
+                    Listed::Id(id) => {
+                        let src = self.backend.post_url(&id);
+                        self.download_page(&src, destination).await
+                    }
+                }
+            })
+            .buffer_unordered(self.concurrency())
+            .try_collect()
+            .await
+    }
 
-        Ok(())
+    /// Search for posts matching `tags`, within the range of pages of the
+    /// session, going from one page to the next as the stream is consumed.
+    ///
+    /// Posts are listed through the API if the session is set to, or scraped
+    /// from their pages otherwise.
+    pub fn search<'a>(&'a self, tags: &str) -> impl Stream<Item = Result<Post>> + 'a {
+        self.list(&Search::new(tags))
+            .map_ok(move |listed| async move {
+                match listed {
+                    Listed::Post(post) => Ok(*post),
+                    Listed::Id(id) => self.get_post(&id).await,
+                }
+            })
+            .try_buffered(self.concurrency())
     }
 
-    async fn get_posts_noapi(
-        &self,
-        base: &str,
-        tag: &str,
-        client: &reqwest::Client,
-    ) -> Result<(), Box<dyn Error>> {
-        let range = RangeInclusive::new(
-            self.options.start.unwrap_or(1),
-            self.options.end.unwrap_or(u64::MAX),
-        );
-        for page in range {
-            println!("Entering posts, page {}...", page);
-            io::stdout().flush().expect("cannot flush stdout");
-
-            let pid = (page - 1) * PID_STEP_LIST;
-            let list_url = format!(
-                "{}&tags={}+{}&pid={}",
-                base,
-                tag,
-                self.options.tags.as_deref().unwrap_or_default(),
-                pid
-            );
-            let res = client.get(list_url).send().await?.text().await?;
-            let list = scraper::Html::parse_document(&res);
-            let selector = scraper::Selector::parse("article.thumbnail-preview a")?;
-            let a_s: Vec<_> = list.select(&selector).collect();
-            if a_s.is_empty() {
-                println!("no elements present.");
-                break;
+    /// List posts found by given search page by page.
+    ///
+    /// If the API refuses a request, e.g. once the daily limit is reached,
+    /// the search goes on from the same page by scraping the site, and so
+    /// does the rest of the session.
+    fn list<'a>(&'a self, search: &Search) -> impl Stream<Item = Result<Listed>> + 'a {
+        let search = search.clone();
+        let start = search.start.or(self.options.start).unwrap_or(1);
+        let end = search.end.or(self.options.end).unwrap_or(u64::MAX);
+        let limit = search.limit.unwrap_or(usize::MAX);
+
+        stream::try_unfold(Some(start), move |page| {
+            let search = search.clone();
+            let tags = search.tags.clone();
+            async move {
+                let Some(page) = page.filter(|p| *p <= end) else {
+                    return Ok(None);
+                };
+                progress!(self, "Entering posts, page {}...", page);
+
+                let api = self.uses_api(&search);
+                let (listed, last) = match self.list_page(&tags, page, api).await {
+                    Err(e) if api && e.is_refusal() && !self.uses_api_only() => {
+                        progress!(self, "The API refused the request: {e}");
+                        progress!(self, "Going on without the API from page {page}...");
+                        self.api_refused.store(true, Ordering::Relaxed);
+                        self.list_page(&tags, page, false).await?
+                    }
+                    res => res?,
+                };
+                if listed.is_empty() {
+                    progress!(self, "no elements present.");
+                    return Ok(None);
+                }
+
+                let next = (!last).then_some(page + 1);
+                Ok(Some((stream::iter(listed).map(Ok), next)))
             }
+        })
+        .try_flatten()
+        .take(limit)
+    }
 
-            self.get_elements_http(a_s, client).await?;
-        }
+    /// List posts on given page, through the API or by scraping the site,
+    /// and tell whether it is the last one.
+    async fn list_page(&self, tags: &str, page: u64, api: bool) -> Result<(Vec<Listed>, bool)> {
+        if api {
+            let list_url =
+                self.backend
+                    .api_list_url(tags, page, API_LIMIT, self.options.api_format)?;
+            let res = self.get_text(&list_url).await?;
+            let listing = self.backend.parse_api_list(&res, &list_url)?;
+            let last = listing.is_last();
+
+            Ok((
+                listing
+                    .posts
+                    .into_iter()
+                    .map(|post| Listed::Post(Box::new(post)))
+                    .collect(),
+                last,
+            ))
+        } else {
+            let list_url = self.backend.html_list_url(tags, page).unwrap_or_default();
+            let res = self.get_text(&list_url).await?;
+            let ids = self.backend.parse_html_list(&res, &list_url)?;
 
-        Ok(())
+            Ok((ids.into_iter().map(Listed::Id).collect(), false))
+        }
     }
 
-    async fn get_views(&self) -> Result<(), Box<dyn Error>> {
+    async fn get_views(&self) -> Result<()> {
         let mut err_count = 0;
-        let client = self.new_client_http()?;
 
         println!("Start getting views...");
         io::stdout().flush().expect("cannot flush stdout");
 
-        let mut buf = String::new();
-        if let Some(f) = &self.options.file {
-            File::open(f)?.read_to_string(&mut buf)?;
+        let buf = match &self.options.file {
+            Some(f) => Self::read_file(f)?,
+            None => String::new(),
         };
-        'outer: for url in buf.lines() {
-            let id = match Self::extract_id_from_url(url) {
+        let mut urls = Vec::new();
+        for url in buf.lines() {
+            let id = match self.extract_id_from_url(url) {
                 Ok(t) => t,
                 Err(e) => {
-                    eprintln!("Error extracting id from {url} : {e}");
+                    eprintln!("Error extracting id: {e}");
                     err_count += 1;
                     continue;
                 }
             };
 
-            let saved = fs::read_dir(self.options.folder.as_deref().unwrap_or("."))?;
-            for file in saved {
-                let name = file?.file_name();
-                if name
-                    .to_str()
-                    .ok_or("invalid character in saved file names")?
-                    .starts_with(id)
-                {
-                    println!("{id} already exists, skipping.");
-                    continue 'outer;
-                }
+            if self.archive.contains(id)? {
+                println!("{id} already exists, skipping.");
+                continue;
             }
 
-            if let Err(e) = self.download(&client, url).await {
-                err_count += 1;
-                eprintln!("failed downloading file: {e}");
-            }
+            urls.push(url);
         }
 
-        println!("Finished getting all views. Error count: {err_count}");
-        
-        Ok(())
-    }
-
-    async fn get_elements_http(
-        &self,
-        a_s: Vec<scraper::ElementRef<'_>>,
-        client: &reqwest::Client,
-    ) -> Result<(), Box<dyn Error>> {
-        'outer: for a in a_s {
-            print!("Extracting information...");
-            io::stdout().flush().expect("cannot flush stdout");
-
-            let href = a
-                .value()
-                .attr("href")
-                .ok_or("cannot find href in thumbnail element")?;
-            let id = Self::extract_id_from_url(href)?;
-
-            let saved = fs::read_dir(self.options.folder.as_deref().unwrap_or("."))?;
-            for file in saved {
-                let name = file?.file_name();
-                if name
-                    .to_str()
-                    .ok_or("invalid character in saved file names")?
-                    .starts_with(id)
-                {
-                    println!("{id} already exists, skipping.");
-                    continue 'outer;
-                }
-            }
-
-            let src = format!("https://gelbooru.com/index.php?page=post&s=view&id={id}");
-            self.download(client, &src).await?;
+        let results: Vec<_> = stream::iter(urls)
+            .map(|url| self.download_page(url, self.destination()))
+            .buffer_unordered(self.concurrency())
+            .collect()
+            .await;
+        for e in results.into_iter().filter_map(Result::err) {
+            err_count += 1;
+            eprintln!("failed downloading file: {e}");
         }
 
+        println!("Finished getting all views. Error count: {err_count}");
+
         Ok(())
     }
 
-    async fn download(&self, client: &reqwest::Client, src: &str) -> Result<(), Box<dyn Error>> {
-        print!("entering {src} ...");
-        io::stdout().flush().expect("cannot flush stdout");
+    /// Get the post with given id from its page.
+    async fn get_post(&self, id: &str) -> Result<Post> {
+        let src = self.backend.post_url(id);
+        let res = self.get_text(&src).await?;
 
-        let res = client.get(src).send().await?.text().await?;
-        let id = Self::extract_id_from_url(src)?;
+        self.backend.extract_post(&res, &src)
+    }
 
-        let file_url = Self::extract_file_url(&res)?;
-        let title = Self::extract_title(&res)?;
-        let folder = self.options.folder.as_deref().unwrap_or(".");
-        let extention = file_url
-            .split('.')
-            .next_back()
-            .ok_or("source file has no extension")?;
-        let path_string = format!("./{}/{} {}.{}", folder, id, title, extention);
+    /// Download the post whose page is at `src`, named after the page title.
+    async fn download_page(&self, src: &str, destination: Destination<'_>) -> Result<()> {
+        println!("Entering {src} ...");
 
-        print!("downloading...");
-        io::stdout().flush().expect("cannot flush stdout");
+        let res = self.get_text(src).await?;
+        let post = self.backend.extract_post(&res, src)?;
+        if !destination.accepts(&post) {
+            println!("{} is not of the wanted ratings, skipping.", post.id);
+            return Ok(());
+        }
+        let title = Self::extract_title(&res, src)?;
 
-        let img_bytes = client.get(file_url).send().await?.bytes().await?;
-        File::create(path_string)?.write_all(&img_bytes)?;
-        println!("complete.");
-        Ok(())
+        self.save_post(&post, Some(title), destination).await
     }
 
-    async fn get_posts_api(&self, base: &str, tag: &str) -> Result<(), Box<dyn Error>> {
-        let range = RangeInclusive::new(
-            self.options.start.unwrap_or(1),
-            self.options.end.unwrap_or(u64::MAX),
-        );
-        for page in range {
-            let list_url = format!(
-                "{}&tags={}+{}&pid={}&limit={}",
-                base,
-                tag,
-                self.options.tags.as_deref().unwrap_or_default(),
-                page,
-                PID_STEP_LIST,
-            );
-            dbg!(&list_url);
-            let res = reqwest::get(list_url).await?.text().await?;
+    /// Download the file of given post into the folder of the session,
+    /// unless the archive tells it has been downloaded already.
+    ///
+    /// The file is named after the template of the session. The page of the
+    /// post is fetched if it needs the title, unless the session is set to be
+    /// `quick`, in which case tags stand for it. It is also fetched if it
+    /// needs tags by type, e.g. `{artist}`, and the post came without them,
+    /// as from the API of most sites.
+    pub async fn download(&self, post: &Post) -> Result<()> {
+        self.download_into(post, self.destination()).await
+    }
 
-            let doc = roxmltree::Document::parse(&res)?;
-            let posts = doc.descendants().filter(|n| n.has_tag_name("post"));
-            for post in posts {
-                self.get_post_by_node(post).await?;
-            }
+    async fn download_into(&self, post: &Post, destination: Destination<'_>) -> Result<()> {
+        let id = post.id.to_string();
+        if self.archive.contains(&id)? {
+            println!("{id} already exists, skipping.");
+            return Ok(());
         }
 
-        Ok(())
-    }
+        let needs_title = self.options.template.uses_title() && !self.options.quick;
+        let needs_tag_types = self.options.template.uses_tag_types() && post.tag_types.is_empty();
+        if !needs_title && !needs_tag_types {
+            return self.save_post(post, None, destination).await;
+        }
 
-    async fn get_post_by_node(&self, post: roxmltree::Node<'_, '_>) -> Result<(), Box<dyn Error>> {
-        let mut nodes = post.descendants();
-
-        let id = nodes
-            .find(|n| n.has_tag_name("id"))
-            .ok_or("cannot find XML tag <id>")?
-            .text()
-            .ok_or("no text in XML tag <id>")?;
-        let name = if self.options.quick {
-            let tags = nodes
-                .find(|n| n.has_tag_name("tags"))
-                .ok_or("cannot find XML tag <tags>")?
-                .text()
-                .ok_or("no text in XML tag <tags>")?;
-            if tags.len() < TITLE_LENGTH_LIMIT {
-                String::from(tags)
-            } else {
-                String::from(&tags[0..TITLE_LENGTH_LIMIT])
-            }
+        let url = self.backend.post_url(&id);
+        let res = self.get_text(&url).await?;
+        let title = needs_title
+            .then(|| Self::extract_title(&res, &url))
+            .transpose()?;
+        if needs_tag_types {
+            let tag_types = self.backend.extract_post(&res, &url)?.tag_types;
+            let post = Post {
+                tag_types,
+                ..post.clone()
+            };
+            self.save_post(&post, title, destination).await
         } else {
-            let url = String::from("https://gelbooru.com/index.php?page=post&s=view&id=") + id;
-            let res = reqwest::get(url).await?.text().await?;
-            Self::extract_title(&res)?.to_string()
-        };
-        let file_url = nodes
-            .find(|n| n.has_tag_name("file_url"))
-            .ok_or("cannot find XML tag <file_url>")?
-            .text()
-            .ok_or("no text in XML tag <file_url>")?;
-        let extension = file_url
-            .split('.')
-            .next_back()
-            .ok_or("source file has no extension")?;
-        let path_string = format!(
-            "./{}/{} {}.{}",
-            self.options.folder.as_deref().unwrap_or("."),
-            id,
-            name,
-            extension,
-        );
+            self.save_post(post, title, destination).await
+        }
+    }
 
-        let path = Path::new(&path_string);
-        if path.exists() {
-            println!("{id} already exists, skipping.");
-            return Ok(());
+    /// Save the file of given post at the path given by the template, along
+    /// with its sidecar files, and record it in the archive.
+    ///
+    /// The file goes into the subfolder `destination` tells, if any, whose
+    /// names are made valid like the ones of the template.
+    async fn save_post(
+        &self,
+        post: &Post,
+        title: Option<&str>,
+        destination: Destination<'_>,
+    ) -> Result<()> {
+        if post.extension().is_none() {
+            return Err(Error::parse(
+                "source file has no extension",
+                &post.id.to_string(),
+            ));
+        }
+        let mut path = PathBuf::from(self.options.folder.as_deref().unwrap_or("."));
+        if let Some(subfolder) = destination.subfolder_of(post) {
+            path.push(sanitize::fit_path(subfolder));
         }
+        path.push(self.options.template.render(post, title));
 
-        let client = self.new_client_http()?;
+        self.save_post_at(post, &path.display().to_string()).await
+    }
 
-        print!("downloading {}...", id);
-        io::stdout().flush().expect("cannot flush stdout");
-        let img_bytes = client.get(file_url).send().await?.bytes().await?;
-        File::create(path_string)?.write_all(&img_bytes)?;
-        println!("complete.");
+    /// Save the file of given post at `path_string`, along with its sidecar
+    /// files, and record it in the archive.
+    async fn save_post_at(&self, post: &Post, path_string: &str) -> Result<()> {
+        let id = post.id.to_string();
+        let file_url = post
+            .file_url
+            .as_deref()
+            .ok_or_else(|| Error::not_found("file url", &id))?;
+
+        if let Some(parent) = Path::new(path_string).parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+
+        println!("Downloading {id}...");
+        let md5 = post.md5.as_deref().or_else(|| md5_from_url(file_url));
+        let md5 = self.download_file(file_url, path_string, md5).await?;
+        if self.wants_sidecars() {
+            self.save_sidecars(path_string, post)?;
+        }
+        self.archive
+            .insert(&id, Some(&md5), Some(Path::new(path_string)))?;
+        println!("{id} complete.");
 
         Ok(())
     }
 
-    pub async fn start(&self) -> Result<(), Box<dyn Error>> {
+    pub async fn start(&self) -> Result<()> {
         match self.options.action {
             Action::GetPosts => self.get_posts().await?,
             Action::GetFavorites => self.get_favorites().await?,
             Action::AddFavorites => self.add_to_favorites().await?,
+            Action::RemoveFavorites => self.remove_from_favorites().await?,
+            Action::SyncFavorites => self.sync_favorites().await?,
             Action::GetViews => self.get_views().await?,
+            Action::Verify => self.verify().await?,
         };
 
         Ok(())
@@ -1,9 +1,26 @@
 const HELP: &str = "Example usage:
-    getbooru get favorites # (WebDriver needed) Get all of your favorites into current directory
-    getbooru add favorites by urls.txt # (WebDriver needed) Add urls in links.txt to your favorites
+    getbooru get favorites # Get all of your favorites into current directory
+    getbooru get favorites webdriver # Get favorites through a WebDriver on localhost:4444 instead
+    getbooru add favorites by urls.txt # Add posts in urls.txt, given by url, id or md5, to your favorites
+    getbooru remove favorites by urls.txt dryrun # List the posts in urls.txt which would be removed from your favorites, drop 'dryrun' to remove them
+    getbooru sync favorites into dir # Print how ./dir/ and your favorites differ, then download the missing favorites
+    getbooru sync favorites into dir push force # Add and remove favorites to match ./dir/ instead
+    getbooru export favorites out favorites.txt # Write the urls of your favorites into favorites.txt, for 'add favorites by' or 'get views by'
+    getbooru export posts with 1boy as csv # Print id, post url, file url and md5 of posts with tag '1boy' (text, csv, jsonl)
     getbooru get posts from 6 to 9 api # Get posts in page 6-9, using API
     getbooru get posts with 1boy into dir # Get posts with tag '1boy' into ./dir/
-Note: 'api' can be combined with 'quick', which speeds up the progress but alternates file names.";
+    getbooru get posts with 1boy jobs 4 # Get posts with tag '1boy', 4 at a time
+    getbooru get posts with 1boy rate 2 filerate 0.5 # Send at most 2 page requests and start at most 0.5 file downloads per second
+    getbooru get posts with 1boy retry 5 # Send each request up to 5 times if it fails
+    getbooru get posts with 1boy site safebooru # Get posts from another site (gelbooru, safebooru, rule34, danbooru, konachan, yandere)
+    getbooru get posts with 1boy at http://localhost:8080 # Get posts from a self-hosted Gelbooru
+    getbooru get posts with 1boy meta taglist # Also save metadata (.json) and tags (.txt) of each post
+    getbooru get posts with 1boy name '{artist}/{id} {md5}.{ext}' # Name files after a template (id, md5, artist, character, copyright, rating, score, date, tags, title, ext)
+    getbooru get posts by tags.txt # Save the results of each line of tags.txt into its own subfolder, add 'nosplit' to keep them together
+    getbooru get posts with 1boy route animated videos # Save posts tagged 'animated' into ./videos/
+    getbooru verify into dir # Check files in ./dir/ against the md5 of their posts, add 'repair' to download broken ones again
+Note: 'api' can be combined with 'quick', which speeds up the progress but alternates file names.
+'api' asks for JSON responses by default, add 'xml' to use XML ones instead.";
 
 #[tokio::main]
 async fn main() {
@@ -49,6 +66,42 @@ async fn main() {
                 return;
             }
         },
+        Some(s) if s == "remove" => match args.next() {
+            Some(s) if s == "favorites" => {
+                opt.remove_favorites();
+            }
+            _ => {
+                println!("{HELP}");
+                return;
+            }
+        },
+        Some(s) if s == "sync" => match args.next() {
+            Some(s) if s == "favorites" => {
+                opt.sync_favorites();
+            }
+            _ => {
+                println!("{HELP}");
+                return;
+            }
+        },
+        Some(s) if s == "export" => {
+            match args.next() {
+                Some(s) if s == "posts" => {
+                    opt.get_posts();
+                }
+                Some(s) if s == "favorites" => {
+                    opt.get_favorites();
+                }
+                _ => {
+                    println!("{HELP}");
+                    return;
+                }
+            }
+            opt.export(getbooru::ExportFormat::Text);
+        }
+        Some(s) if s == "verify" => {
+            opt.verify();
+        }
         Some(_) => {
             println!("{HELP}");
             return;
@@ -86,6 +139,68 @@ async fn main() {
                     panic!("Option \"into\" needs an argument.");
                 }
             }
+            "site" => {
+                if let Some(p) = args.next() {
+                    opt.site(p.parse().unwrap());
+                } else {
+                    panic!("Option \"site\" needs an argument.");
+                }
+            }
+            "at" => {
+                if let Some(p) = args.next() {
+                    opt.base_url(p.as_str());
+                } else {
+                    panic!("Option \"at\" needs an argument.");
+                }
+            }
+            "cdn" => {
+                if let Some(p) = args.next() {
+                    opt.cdn_host(p.as_str());
+                } else {
+                    panic!("Option \"cdn\" needs an argument.");
+                }
+            }
+            "name" => {
+                if let Some(p) = args.next() {
+                    opt.template(p.parse().unwrap());
+                } else {
+                    panic!("Option \"name\" needs an argument.");
+                }
+            }
+            "route" => {
+                if let (Some(tag), Some(folder)) = (args.next(), args.next()) {
+                    opt.rule(tag.as_str(), folder.as_str());
+                } else {
+                    panic!("Option \"route\" needs two arguments.");
+                }
+            }
+            "split" => {
+                opt.split(true);
+            }
+            "nosplit" => {
+                opt.split(false);
+            }
+            "as" => {
+                if let Some(p) = args.next() {
+                    opt.export(p.parse().unwrap());
+                } else {
+                    panic!("Option \"as\" needs an argument.");
+                }
+            }
+            "out" => {
+                if let Some(p) = args.next() {
+                    opt.output(p.as_str());
+                } else {
+                    panic!("Option \"out\" needs an argument.");
+                }
+            }
+            "archive" => {
+                if let Some(p) = args.next() {
+                    opt.archive(p.as_str());
+                } else {
+                    panic!("Option \"archive\" needs an argument.");
+                }
+            }
             "with" => {
                 if let Some(p) = args.next() {
                     opt.tags(p.as_str());
@@ -93,12 +208,64 @@ async fn main() {
                     panic!("Option \"with\" needs an argument.");
                 }
             }
+            "jobs" => {
+                if let Some(n) = args.next() {
+                    opt.concurrency(n.parse::<usize>().unwrap());
+                } else {
+                    panic!("Option \"jobs\" needs an argument.");
+                }
+            }
+            "rate" => {
+                if let Some(n) = args.next() {
+                    opt.page_rate(n.parse::<f64>().unwrap());
+                } else {
+                    panic!("Option \"rate\" needs an argument.");
+                }
+            }
+            "filerate" => {
+                if let Some(n) = args.next() {
+                    opt.file_rate(n.parse::<f64>().unwrap());
+                } else {
+                    panic!("Option \"filerate\" needs an argument.");
+                }
+            }
+            "retry" => {
+                if let Some(n) = args.next() {
+                    opt.max_attempts(n.parse::<u32>().unwrap());
+                } else {
+                    panic!("Option \"retry\" needs an argument.");
+                }
+            }
             "api" => {
                 opt.api(true);
             }
+            "xml" => {
+                opt.api_format(getbooru::ApiFormat::Xml);
+            }
             "quick" => {
                 opt.quick(true);
             }
+            "webdriver" => {
+                opt.webdriver(true);
+            }
+            "repair" => {
+                opt.repair(true);
+            }
+            "dryrun" => {
+                opt.dry_run(true);
+            }
+            "push" => {
+                opt.push(true);
+            }
+            "force" => {
+                opt.force(true);
+            }
+            "meta" => {
+                opt.metadata(true);
+            }
+            "taglist" => {
+                opt.tag_list(true);
+            }
             _ => {
                 println!("{HELP}");
                 return;
@@ -106,5 +273,8 @@ async fn main() {
         }
     }
 
-    opt.create().start().await.unwrap();
+    if let Err(e) = opt.create().start().await {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
 }
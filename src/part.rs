@@ -0,0 +1,180 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+};
+
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Extension of files being downloaded, and of what is needed to resume
+/// them once appended to it.
+pub(crate) const PART_EXTENSION: &str = "part";
+const INFO_EXTENSION: &str = "part.json";
+
+/// A download in progress, written to `<path>.part` and renamed to `path`
+/// once complete.
+///
+/// If the server allows it, the size and validators of the file are kept in
+/// `<path>.part.json`, so an interrupted download can be resumed with a
+/// `Range` request rather than started over.
+#[derive(Debug)]
+pub(crate) struct PartFile {
+    path: String,
+    part: String,
+    info: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Validator {
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validator {
+    /// Read the validator of a full response, if the server accepts to send
+    /// parts of it.
+    fn from_response(res: &Response) -> Option<Self> {
+        let accepts_ranges = res
+            .headers()
+            .get(header::ACCEPT_RANGES)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"bytes"));
+        if !accepts_ranges {
+            return None;
+        }
+
+        Some(Self {
+            size: res.content_length()?,
+            etag: header_str(res, header::ETAG),
+            last_modified: header_str(res, header::LAST_MODIFIED),
+        })
+    }
+
+    /// Whether a partial response continues the file at `offset`.
+    fn is_continued_by(&self, res: &Response, offset: u64) -> bool {
+        // `Content-Range: bytes <start>-<end>/<size>`
+        let range = header_str(res, header::CONTENT_RANGE);
+        let Some((start, size)) = range
+            .as_deref()
+            .and_then(|r| r.strip_prefix("bytes "))
+            .and_then(|r| r.split_once('/'))
+            .and_then(|(range, size)| Some((range.split_once('-')?.0, size)))
+        else {
+            return false;
+        };
+        let same = |ours: &Option<String>, name| match (ours, header_str(res, name)) {
+            (Some(ours), Some(theirs)) => *ours == theirs,
+            _ => true,
+        };
+
+        start.parse() == Ok(offset)
+            && size.parse() == Ok(self.size)
+            && same(&self.etag, header::ETAG)
+            && same(&self.last_modified, header::LAST_MODIFIED)
+    }
+}
+
+impl PartFile {
+    pub(crate) fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            part: format!("{path}.{PART_EXTENSION}"),
+            info: format!("{path}.{INFO_EXTENSION}"),
+        }
+    }
+
+    /// Number of bytes already received by an earlier attempt which can be
+    /// resumed, along with the validator of the file.
+    fn resumable(&self) -> Option<(u64, Validator)> {
+        let info = fs::read_to_string(&self.info).ok()?;
+        let validator: Validator = serde_json::from_str(&info).ok()?;
+        let offset = fs::metadata(&self.part).ok()?.len();
+
+        (0 < offset && offset < validator.size).then_some((offset, validator))
+    }
+
+    /// Ask for the rest of the file only, if an earlier attempt can be
+    /// resumed. Servers answer with the whole file if it has changed since.
+    pub(crate) fn prepare(&self, req: RequestBuilder) -> RequestBuilder {
+        let Some((offset, validator)) = self.resumable() else {
+            return req;
+        };
+
+        let req = req.header(header::RANGE, format!("bytes={offset}-"));
+        match validator.etag.or(validator.last_modified) {
+            Some(v) => req.header(header::IF_RANGE, v),
+            None => req,
+        }
+    }
+
+    /// Open the part file to write the body of `res` into, returning the
+    /// offset it starts at.
+    ///
+    /// A partial response is appended to what was received before, after
+    /// checking that it belongs to the same file. A full one starts over.
+    /// Either way, the file can be read from the start.
+    pub(crate) fn open(&self, res: &Response, url: &str) -> Result<(File, u64)> {
+        if res.status() == StatusCode::PARTIAL_CONTENT {
+            return match self.resumable() {
+                Some((offset, validator)) if validator.is_continued_by(res, offset) => {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .append(true)
+                        .open(&self.part)
+                        .map_err(|e| Error::io(&self.part, e))?;
+                    Ok((file, offset))
+                }
+                _ => {
+                    self.discard()?;
+                    Err(Error::Changed {
+                        url: url.to_owned(),
+                    })
+                }
+            };
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.part)
+            .map_err(|e| Error::io(&self.part, e))?;
+        match Validator::from_response(res) {
+            Some(validator) => {
+                let info = serde_json::to_string(&validator)
+                    .map_err(|e| Error::parse(e.to_string(), &self.info))?;
+                fs::write(&self.info, info).map_err(|e| Error::io(&self.info, e))?;
+            }
+            None => remove(&self.info)?,
+        }
+
+        Ok((file, 0))
+    }
+
+    /// Move the complete file into place.
+    pub(crate) fn finish(&self) -> Result<()> {
+        fs::rename(&self.part, &self.path).map_err(|e| Error::io(&self.path, e))?;
+        remove(&self.info)
+    }
+
+    /// Remove what has been received so far.
+    pub(crate) fn discard(&self) -> Result<()> {
+        remove(&self.part)?;
+        remove(&self.info)
+    }
+}
+
+fn header_str(res: &Response, name: header::HeaderName) -> Option<String> {
+    Some(res.headers().get(name)?.to_str().ok()?.to_owned())
+}
+
+/// Remove the file at `path`, if any.
+fn remove(path: &str) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::io(path, e)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::{backend::Metadata, Error, Result};
+
+/// Content rating of a post.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    General,
+    Sensitive,
+    Questionable,
+    Explicit,
+}
+
+impl FromStr for Rating {
+    type Err = Error;
+
+    /// Parse a rating in any of the spellings boorus use, e.g. `s`, `safe`
+    /// and `general` all being the mildest one on different sites.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "g" | "general" | "safe" => Ok(Self::General),
+            "s" | "sensitive" => Ok(Self::Sensitive),
+            "q" | "questionable" => Ok(Self::Questionable),
+            "e" | "explicit" => Ok(Self::Explicit),
+            _ => Err(Error::parse("unknown rating", s)),
+        }
+    }
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::General => "general",
+            Self::Sensitive => "sensitive",
+            Self::Questionable => "questionable",
+            Self::Explicit => "explicit",
+        })
+    }
+}
+
+/// A post, as listed by the API or scraped from its page.
+///
+/// Fields are named after the Gelbooru API, and also accept the names other
+/// sites use. Whatever a site exposes beyond them is kept in `extra`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Post {
+    #[serde(deserialize_with = "de_u64")]
+    pub id: u64,
+    #[serde(default, alias = "hash", deserialize_with = "de_string")]
+    pub md5: Option<String>,
+    #[serde(default, deserialize_with = "de_string")]
+    pub file_url: Option<String>,
+    #[serde(default, alias = "large_file_url", deserialize_with = "de_string")]
+    pub sample_url: Option<String>,
+    #[serde(default, alias = "preview_file_url", deserialize_with = "de_string")]
+    pub preview_url: Option<String>,
+    #[serde(default, alias = "tag_string", deserialize_with = "de_tags")]
+    pub tags: Vec<String>,
+    /// Tags grouped by their type, e.g. `artist` or `character`, if known.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tag_types: BTreeMap<String, Vec<String>>,
+    #[serde(default, deserialize_with = "de_rating")]
+    pub rating: Option<Rating>,
+    #[serde(default, deserialize_with = "de_i64")]
+    pub score: Option<i64>,
+    #[serde(default, alias = "image_width", deserialize_with = "de_u64_opt")]
+    pub width: Option<u64>,
+    #[serde(default, alias = "image_height", deserialize_with = "de_u64_opt")]
+    pub height: Option<u64>,
+    /// Size of the file in bytes, if the site tells.
+    #[serde(default, deserialize_with = "de_u64_opt")]
+    pub file_size: Option<u64>,
+    #[serde(default, deserialize_with = "de_string")]
+    pub source: Option<String>,
+    /// Creation time, in the format of the site.
+    #[serde(default, deserialize_with = "de_string")]
+    pub created_at: Option<String>,
+    #[serde(default, deserialize_with = "de_u64_opt")]
+    pub parent_id: Option<u64>,
+    #[serde(default, deserialize_with = "de_bool")]
+    pub has_children: bool,
+    /// Name or id of the uploader.
+    #[serde(
+        default,
+        alias = "author",
+        alias = "uploader_id",
+        deserialize_with = "de_string"
+    )]
+    pub owner: Option<String>,
+    #[serde(flatten)]
+    pub extra: Metadata,
+}
+
+impl Post {
+    /// Build a post out of the fields a site exposes.
+    pub fn from_metadata(meta: Metadata) -> Result<Self> {
+        let context = meta.get("id").map(Value::to_string).unwrap_or_default();
+        let mut post: Self = serde_json::from_value(Value::Object(meta))
+            .map_err(|e| Error::parse(format!("invalid post: {e}"), &context))?;
+
+        // Group tags by their type, from `tag_string_artist` on Danbooru or
+        // `tags_artist` when scraped.
+        let typed: Vec<_> = post
+            .extra
+            .keys()
+            .filter(|k| k.starts_with("tag_string_") || k.starts_with("tags_"))
+            .cloned()
+            .collect();
+        for key in typed {
+            let kind = key
+                .trim_start_matches("tag_string_")
+                .trim_start_matches("tags_");
+            if let Some(value) = post.extra.remove(&key) {
+                let tags = de_tags(value).unwrap_or_default();
+                if !tags.is_empty() {
+                    post.tag_types.insert(kind.to_owned(), tags);
+                }
+            }
+        }
+
+        Ok(post)
+    }
+
+    /// Tags of given type, e.g. `artist`, empty if unknown.
+    pub fn tags_of(&self, kind: &str) -> &[String] {
+        self.tag_types.get(kind).map_or(&[], Vec::as_slice)
+    }
+
+    /// Extension of the file, taken from its url.
+    pub fn extension(&self) -> Option<&str> {
+        let name = self.file_url.as_deref()?.rsplit('/').next()?;
+        let (_, extension) = name.rsplit_once('.')?;
+
+        Some(extension.split(['?', '#']).next().unwrap_or(extension))
+    }
+}
+
+fn de_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(d)? {
+        Value::String(s) if !s.is_empty() => Some(s),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+fn de_u64<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    de_u64_opt(d)?.ok_or_else(|| serde::de::Error::custom("expected a non-negative integer"))
+}
+
+fn de_u64_opt<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    Ok(match Value::deserialize(d)? {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+fn de_i64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<i64>, D::Error> {
+    Ok(match Value::deserialize(d)? {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+fn de_bool<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    Ok(match Value::deserialize(d)? {
+        Value::Bool(b) => b,
+        Value::Number(n) => n.as_u64().is_some_and(|n| n != 0),
+        Value::String(s) => matches!(s.trim(), "true" | "1"),
+        _ => false,
+    })
+}
+
+fn de_rating<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Rating>, D::Error> {
+    Ok(de_string(d)?.and_then(|s| s.parse().ok()))
+}
+
+/// Accept tags either as one string separated by spaces, or as an array.
+fn de_tags<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    Ok(match Value::deserialize(d)? {
+        Value::String(s) => s.split_whitespace().map(str::to_owned).collect(),
+        Value::Array(a) => a
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::String(s) => Some(s),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    })
+}
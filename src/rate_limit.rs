@@ -0,0 +1,67 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token bucket spreading requests to at most `rate` per second, allowing
+/// bursts of up to `rate` requests after a pause.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    rate: Option<f64>,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter letting `rate` requests per second through, or any
+    /// number of them if `None`.
+    pub(crate) fn new(rate: Option<f64>) -> Self {
+        let rate = rate.filter(|r| r.is_finite() && *r > 0.0);
+
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate.map_or(0.0, burst),
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until the next request may be sent.
+    ///
+    /// Tokens are taken in the order callers arrive, so concurrent requests
+    /// are queued rather than all woken at once.
+    pub(crate) async fn acquire(&self) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst(rate));
+            bucket.updated = now;
+            bucket.tokens -= 1.0;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+fn burst(rate: f64) -> f64 {
+    rate.max(1.0)
+}
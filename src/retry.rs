@@ -0,0 +1,105 @@
+use std::time::{Duration, SystemTime};
+
+use reqwest::{header, StatusCode};
+
+use crate::Error;
+
+/// When and how often failed requests are sent again.
+///
+/// Connection failures and responses with a transient status code are
+/// retried, waiting `backoff` before the second attempt and twice as long
+/// before each of the next ones, unless the server tells how long to wait
+/// with `Retry-After`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of attempts in total, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub backoff: Duration,
+    /// Upper bound of the backoff. Requests whose server asks to wait longer
+    /// fail as rate limited instead.
+    pub max_backoff: Duration,
+    /// Status codes worth retrying.
+    pub transient: Vec<StatusCode>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            transient: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy sending every request only once.
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn is_transient(&self, status: StatusCode) -> bool {
+        self.transient.contains(&status)
+    }
+
+    /// Whether a request which could not be completed is worth retrying.
+    pub(crate) fn is_transient_error(e: &Error) -> bool {
+        match e {
+            Error::Http { source, .. } => {
+                source.is_timeout()
+                    || source.is_connect()
+                    || source.is_request()
+                    || source.is_body()
+            }
+            Error::Incomplete { .. } | Error::Changed { .. } | Error::Checksum { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Delay before the attempt following the `attempt`th one.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// Delay asked by the server with `Retry-After`, in seconds or as a date,
+    /// on responses which are meant to carry it. It is not capped by
+    /// `max_backoff`.
+    pub(crate) fn retry_after(&self, res: &reqwest::Response) -> Option<Duration> {
+        if !matches!(
+            res.status(),
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        ) {
+            return None;
+        }
+
+        let value = res
+            .headers()
+            .get(header::RETRY_AFTER)?
+            .to_str()
+            .ok()?
+            .trim();
+        let delay = match value.parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => httpdate::parse_http_date(value)
+                .ok()?
+                .duration_since(SystemTime::now())
+                .unwrap_or_default(),
+        };
+
+        Some(delay)
+    }
+}
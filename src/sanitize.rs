@@ -0,0 +1,206 @@
+use std::borrow::Cow;
+
+/// Maximum length in bytes of the value of a placeholder.
+const VALUE_LENGTH_LIMIT: usize = 100;
+/// Maximum length in bytes of a file or folder name, leaving room for the
+/// `.part.json` of downloads in progress within the 255 bytes most
+/// filesystems allow.
+const NAME_LENGTH_LIMIT: usize = 240;
+
+/// Names Windows reserves for devices, whatever the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turn a value taken from a site, such as a title or tags, into text which
+/// can be part of a file name on any platform.
+///
+/// HTML entities are decoded, characters forbidden in file names replaced
+/// with `_`, and the result truncated to a fixed number of bytes.
+pub(crate) fn sanitize(value: &str) -> String {
+    let value: String = decode_entities(value)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '?' | '%' | '*' | ':' | '|' | '"' | '<' | '>' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    truncate(value.trim(), VALUE_LENGTH_LIMIT)
+        .trim_end_matches(['.', ' '])
+        .to_owned()
+}
+
+/// Make a whole file or folder name valid on any platform.
+///
+/// Trailing dots and spaces are removed, reserved names are prefixed with
+/// `_`, and names too long are truncated, keeping their extension.
+pub(crate) fn fit_name(name: &str) -> String {
+    let mut name = name.trim_end_matches(['.', ' ']).to_owned();
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        name.insert(0, '_');
+    }
+
+    if name.len() > NAME_LENGTH_LIMIT {
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if extension.len() < 16 => (stem, Some(extension)),
+            _ => (name.as_str(), None),
+        };
+        let budget = NAME_LENGTH_LIMIT - extension.map_or(0, |e| e.len() + 1);
+        let stem = truncate(stem, budget).trim_end_matches(['.', ' ']);
+        name = match extension {
+            Some(extension) => format!("{stem}.{extension}"),
+            None => stem.to_owned(),
+        };
+    }
+
+    name
+}
+
+/// Make a relative folder path valid on any platform, fitting each of its
+/// names with [`fit_name`].
+///
+/// Empty, `.` and `..` names are left out, so that the path stays within the
+/// folder it is joined to.
+pub(crate) fn fit_path(path: &str) -> String {
+    path.split(['/', '\\'])
+        .map(fit_name)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Cut `s` to at most `limit` bytes, on a character boundary.
+fn truncate(s: &str, limit: usize) -> &str {
+    let mut end = s.len().min(limit);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+/// Decode the HTML entities sites leave in titles and tags, such as `&amp;`
+/// or `&#039;`. Unknown entities are kept as they are.
+fn decode_entities(s: &str) -> Cow<'_, str> {
+    if !s.contains('&') {
+        return Cow::Borrowed(s);
+    }
+
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = entity.strip_prefix('#')?;
+                let code = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+
+        match (entity, c) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    Cow::Owned(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_on_char_boundary() {
+        // 'あ' takes 3 bytes, so 100 bytes end within the 34th one.
+        let title = "あ".repeat(34);
+        assert_eq!(truncate(&title, 100), "あ".repeat(33));
+        assert_eq!(truncate(&title, 102), title);
+        assert_eq!(truncate("abc", 10), "abc");
+    }
+
+    #[test]
+    fn sanitize_japanese_at_limit() {
+        let value = sanitize(&"東方".repeat(20));
+        assert!(value.len() <= VALUE_LENGTH_LIMIT);
+        assert_eq!(value, "東方".repeat(20)[..99]);
+    }
+
+    #[test]
+    fn sanitize_forbidden_characters() {
+        assert_eq!(sanitize("a/b\\c?d%e*f:g|h\"i<j>k"), "a_b_c_d_e_f_g_h_i_j_k");
+        assert_eq!(sanitize("tab\there"), "tab_here");
+    }
+
+    #[test]
+    fn sanitize_decodes_entities() {
+        assert_eq!(sanitize("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(sanitize("it&#039;s"), "it's");
+        assert_eq!(sanitize("&#x41;&lt;"), "A_");
+        assert_eq!(sanitize("&unknown; &"), "&unknown; &");
+    }
+
+    #[test]
+    fn sanitize_trims_dots_and_spaces() {
+        assert_eq!(sanitize("  title. . "), "title");
+    }
+
+    #[test]
+    fn fit_name_reserved() {
+        assert_eq!(fit_name("con.jpg"), "_con.jpg");
+        assert_eq!(fit_name("LPT1"), "_LPT1");
+        assert_eq!(fit_name("console.jpg"), "console.jpg");
+    }
+
+    #[test]
+    fn fit_name_trailing_dots_and_spaces() {
+        assert_eq!(fit_name("name. ."), "name");
+        assert_eq!(fit_name("folder "), "folder");
+    }
+
+    #[test]
+    fn fit_path_names() {
+        assert_eq!(fit_path("artists/con/aux.txt"), "artists/_con/_aux.txt");
+        assert_eq!(fit_path("videos. /2024 "), "videos/2024");
+        assert_eq!(fit_path("../a/./b//c\\..\\d"), "a/b/c/d");
+        assert_eq!(fit_path(".."), "");
+    }
+
+    #[test]
+    fn fit_name_keeps_extension() {
+        let name = fit_name(&format!("{}.jpg", "あ".repeat(100)));
+        assert!(name.len() <= NAME_LENGTH_LIMIT);
+        assert!(name.ends_with(".jpg"));
+        assert!(name.starts_with("あ"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::{collections::BTreeSet, io, path::Path};
+
+use futures_util::{stream, StreamExt, TryStreamExt};
+
+use crate::{Error, Result, Session};
+
+impl Session {
+    /// Compare the favorites of current user with the posts saved in the
+    /// folder of the session, i.e. recorded in the archive with a file which
+    /// is still there, print the difference, then download the favorites
+    /// missing from the folder.
+    ///
+    /// If the session is set to `push`, the favorites are changed to match
+    /// the folder instead: posts only found in the folder are added to them,
+    /// and the ones missing from it are removed. Removing any favorite needs
+    /// the session to be set to `force`, once a dry run has shown which.
+    /// A dry run stops after the difference.
+    pub(crate) async fn sync_favorites(&self) -> Result<()> {
+        println!("Start syncing favorites...");
+
+        let folder = self.options.folder.as_deref().unwrap_or(".");
+        if !Path::new(folder).is_dir() {
+            return Err(Error::io(
+                folder,
+                io::Error::new(io::ErrorKind::NotFound, "folder does not exist"),
+            ));
+        }
+        let local: BTreeSet<u64> = self
+            .archive
+            .saved_in(Path::new(folder))?
+            .iter()
+            .filter_map(|(id, _)| id.parse().ok())
+            .collect();
+
+        let remote: Vec<_> = self.favorites()?.try_collect().await?;
+        let remote_ids: BTreeSet<u64> = remote.iter().filter_map(|l| l.id().parse().ok()).collect();
+
+        let only_remote: Vec<_> = remote_ids.difference(&local).collect();
+        let only_local: Vec<_> = local.difference(&remote_ids).collect();
+        let join = |ids: &[&u64]| {
+            ids.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        println!(
+            "Only in favorites ({}): {}",
+            only_remote.len(),
+            join(&only_remote)
+        );
+        println!(
+            "Only in {folder} ({}): {}",
+            only_local.len(),
+            join(&only_local)
+        );
+        println!("In both: {}", remote_ids.intersection(&local).count());
+
+        if self.options.dry_run {
+            println!("Finished syncing favorites, nothing changed.");
+            return Ok(());
+        }
+
+        if self.options.push {
+            if !only_remote.is_empty() && !self.options.force {
+                return Err(Error::Unconfirmed {
+                    what: format!("remove {} favorites", only_remote.len()),
+                });
+            }
+
+            let added: Vec<_> = only_local.iter().map(ToString::to_string).collect();
+            let removed: Vec<_> = only_remote.iter().map(ToString::to_string).collect();
+            let (added, _, add_errors) = self
+                .change_all(added.iter().map(String::as_str), true, None)
+                .await;
+            let (removed, _, remove_errors) = self
+                .change_all(removed.iter().map(String::as_str), false, None)
+                .await;
+
+            println!(
+                "Finished syncing favorites. Added: {added}, removed: {removed}, error count: {}",
+                add_errors + remove_errors
+            );
+        } else {
+            let missing = remote
+                .into_iter()
+                .filter(|l| l.id().parse().map_or(true, |id| !local.contains(&id)));
+            self.download_listed(stream::iter(missing).map(Ok), self.destination())
+                .await?;
+
+            println!("Finished syncing favorites.");
+        }
+
+        Ok(())
+    }
+}
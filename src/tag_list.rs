@@ -0,0 +1,336 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Post, Rating, Result};
+
+/// A tag list given with `by`, holding one search per line.
+///
+/// ```text
+/// # Comments start with '#', blank lines are skipped.
+/// 1girl solo
+/// artist_name -> artists/artist_name
+/// 1boy | from 2 to 5 rating general,sensitive noapi limit 100
+/// tagged: animated -> videos
+/// include more_tags.txt
+/// ```
+///
+/// A search followed by `-> folder` saves its results into that subfolder.
+/// Options of the search follow a `|`, before any `->`:
+///
+/// - `from N` and `to N`, the range of pages
+/// - `rating R[,R...]`, the only ratings to save
+/// - `into folder`, same as `-> folder`
+/// - `api` or `noapi`, whether to list posts through the API
+/// - `limit N`, the number of posts to list at most
+///
+/// A `tagged: tag -> folder` line saves posts having `tag` into `folder`,
+/// whichever search finds them. `include path` reads another tag list, its
+/// path being relative to the including one.
+#[derive(Debug, Default)]
+pub(crate) struct TagList {
+    pub(crate) searches: Vec<Search>,
+    pub(crate) rules: Vec<Rule>,
+}
+
+/// A search, either from a line of a tag list or from the session options.
+///
+/// Options left to `None` are taken from the session.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Search {
+    pub(crate) tags: String,
+    /// Subfolder to save the results into.
+    pub(crate) destination: Option<String>,
+    pub(crate) start: Option<u64>,
+    pub(crate) end: Option<u64>,
+    pub(crate) api: Option<bool>,
+    pub(crate) limit: Option<usize>,
+    /// Ratings of the posts to save, all of them if empty.
+    pub(crate) ratings: Vec<Rating>,
+}
+
+/// Posts tagged `tag` go to `folder`.
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub(crate) tag: String,
+    pub(crate) folder: String,
+}
+
+impl TagList {
+    /// Read and check the tag list at `path`, along with the ones it
+    /// includes, before any request is made.
+    pub(crate) fn load(path: &str) -> Result<Self> {
+        let mut list = Self::default();
+        list.read(Path::new(path), &mut Vec::new())?;
+
+        Ok(list)
+    }
+
+    fn read(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<()> {
+        let context = path.display().to_string();
+        let canonical = fs::canonicalize(path).map_err(|e| Error::io(path, e))?;
+        if stack.contains(&canonical) {
+            return Err(Error::parse("tag list includes itself", &context));
+        }
+        let buf = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+
+        stack.push(canonical);
+        for (i, line) in buf.lines().enumerate() {
+            let error = |what: String| Error::parse(format!("line {}: {what}", i + 1), &context);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(include) = line.strip_prefix("include ") {
+                let include = path.parent().unwrap_or(Path::new("")).join(include.trim());
+                self.read(&include, stack)?;
+            } else if let Some(rule) = line.strip_prefix("tagged:") {
+                let (tag, folder) = rule
+                    .split_once("->")
+                    .ok_or_else(|| error("expected 'tagged: tag -> folder'".into()))?;
+                let (tag, folder) = (tag.trim(), folder.trim());
+                if tag.is_empty() || folder.is_empty() {
+                    return Err(error("expected 'tagged: tag -> folder'".into()));
+                }
+                self.rules.push(Rule {
+                    tag: tag.to_owned(),
+                    folder: folder.to_owned(),
+                });
+            } else {
+                self.searches.push(Search::parse(line).map_err(error)?);
+            }
+        }
+        stack.pop();
+
+        Ok(())
+    }
+}
+
+impl Search {
+    pub(crate) fn new(tags: &str) -> Self {
+        Self {
+            tags: tags.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Parse a line of a tag list, `tags [| options] [-> folder]`.
+    fn parse(line: &str) -> Result<Self, String> {
+        let (line, arrow) = match line.split_once("->") {
+            Some((line, folder)) => (line, Some(folder.trim())),
+            None => (line, None),
+        };
+        let (tags, options) = line.split_once('|').unwrap_or((line, ""));
+
+        let mut search = Self::new(tags.trim());
+        if search.tags.is_empty() {
+            return Err("no tags to search".into());
+        }
+
+        let mut words = options.split_whitespace();
+        while let Some(word) = words.next() {
+            let mut arg = || {
+                words
+                    .next()
+                    .ok_or_else(|| format!("option '{word}' needs an argument"))
+            };
+            let number = |s: &str| {
+                s.parse::<u64>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("'{s}' is not a positive number"))
+            };
+            match word {
+                "from" => search.start = Some(number(arg()?)?),
+                "to" => search.end = Some(number(arg()?)?),
+                "limit" => search.limit = Some(number(arg()?)? as usize),
+                "into" => search.destination = Some(arg()?.to_owned()),
+                "api" => search.api = Some(true),
+                "noapi" => search.api = Some(false),
+                "rating" => {
+                    for rating in arg()?.split(',') {
+                        let rating = rating
+                            .parse()
+                            .map_err(|_| format!("unknown rating '{rating}'"))?;
+                        search.ratings.push(rating);
+                    }
+                }
+                _ => return Err(format!("unknown option '{word}'")),
+            }
+        }
+
+        match arrow {
+            Some("") => return Err("no folder after '->'".into()),
+            Some(folder) if folder.contains('|') => {
+                return Err("options must come before '->'".into());
+            }
+            Some(_) if search.destination.is_some() => {
+                return Err("both 'into' and '->' are given".into());
+            }
+            Some(folder) => search.destination = Some(folder.to_owned()),
+            None => {}
+        }
+        if let (Some(start), Some(end)) = (search.start, search.end) {
+            if start > end {
+                return Err(format!("page {start} comes after page {end}"));
+            }
+        }
+
+        Ok(search)
+    }
+}
+
+/// Which of the posts found by a search are saved, and where, relative to
+/// the folder of the session.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Destination<'a> {
+    pub(crate) subfolder: Option<&'a str>,
+    pub(crate) rules: &'a [Rule],
+    /// Ratings of the posts to save, all of them if empty.
+    pub(crate) ratings: &'a [Rating],
+}
+
+impl<'a> Destination<'a> {
+    /// Whether given post is to be saved.
+    pub(crate) fn accepts(&self, post: &Post) -> bool {
+        match post.rating {
+            Some(rating) => self.ratings.is_empty() || self.ratings.contains(&rating),
+            None => true,
+        }
+    }
+
+    /// Subfolder of given post: the one of the first rule matching its tags,
+    /// or the one of the search.
+    pub(crate) fn subfolder_of(&self, post: &Post) -> Option<&'a str> {
+        self.rules
+            .iter()
+            .find(|rule| post.tags.contains(&rule.tag))
+            .map(|rule| rule.folder.as_str())
+            .or(self.subfolder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `files` into a new temporary folder named after `test`.
+    fn write_lists(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("getbooru-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn load_error(test: &str, content: &str) -> String {
+        let dir = write_lists(test, &[("tags.txt", content)]);
+        let error = TagList::load(dir.join("tags.txt").to_str().unwrap()).unwrap_err();
+        fs::remove_dir_all(dir).unwrap();
+        error.to_string()
+    }
+
+    #[test]
+    fn parse_options() {
+        let search =
+            Search::parse("1boy solo | from 2 to 5 rating general,s noapi limit 100 into boys")
+                .unwrap();
+        assert_eq!(search.tags, "1boy solo");
+        assert_eq!(search.start, Some(2));
+        assert_eq!(search.end, Some(5));
+        assert_eq!(search.ratings, [Rating::General, Rating::Sensitive]);
+        assert_eq!(search.api, Some(false));
+        assert_eq!(search.limit, Some(100));
+        assert_eq!(search.destination.as_deref(), Some("boys"));
+    }
+
+    #[test]
+    fn parse_arrow() {
+        let search = Search::parse("artist_name | api -> artists/artist_name").unwrap();
+        assert_eq!(search.tags, "artist_name");
+        assert_eq!(search.api, Some(true));
+        assert_eq!(search.destination.as_deref(), Some("artists/artist_name"));
+
+        let search = Search::parse("1girl").unwrap();
+        assert_eq!(search.destination, None);
+        assert!(search.ratings.is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |line| Search::parse(line).unwrap_err();
+        assert_eq!(
+            error("tags | into a -> b"),
+            "both 'into' and '->' are given"
+        );
+        assert_eq!(
+            error("tags -> dir | from 2"),
+            "options must come before '->'"
+        );
+        assert_eq!(error("tags ->"), "no folder after '->'");
+        assert_eq!(error("tags | from 5 to 2"), "page 5 comes after page 2");
+        assert_eq!(error("tags | from 0"), "'0' is not a positive number");
+        assert_eq!(error("tags | limit"), "option 'limit' needs an argument");
+        assert_eq!(error(" | from 2"), "no tags to search");
+    }
+
+    #[test]
+    fn unknown_words_with_line_numbers() {
+        let error = load_error("option", "# comment\n\n1girl\n1boy | sort score\n");
+        assert!(
+            error.starts_with("line 4: unknown option 'sort'"),
+            "{error}"
+        );
+
+        let error = load_error("rating", "1girl | rating general,lewd\n");
+        assert!(
+            error.starts_with("line 1: unknown rating 'lewd'"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = write_lists(
+            "cycle",
+            &[
+                ("a.txt", "1girl\ninclude b.txt\n"),
+                ("b.txt", "include a.txt\n"),
+            ],
+        );
+        let error = TagList::load(dir.join("a.txt").to_str().unwrap()).unwrap_err();
+        fs::remove_dir_all(dir).unwrap();
+        assert!(
+            error.to_string().starts_with("tag list includes itself"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn include_relative() {
+        let dir = write_lists(
+            "include",
+            &[
+                (
+                    "tags.txt",
+                    "1girl\ninclude more/tags.txt\ntagged: solo -> solo\n",
+                ),
+                ("more/tags.txt", "1boy -> boys\ninclude other.txt\n"),
+                ("more/other.txt", "scenery\n"),
+            ],
+        );
+        let list = TagList::load(dir.join("tags.txt").to_str().unwrap()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        let tags: Vec<_> = list.searches.iter().map(|s| s.tags.as_str()).collect();
+        assert_eq!(tags, ["1girl", "1boy", "scenery"]);
+        assert_eq!(list.searches[1].destination.as_deref(), Some("boys"));
+        assert_eq!(list.rules[0].tag, "solo");
+        assert_eq!(list.rules[0].folder, "solo");
+    }
+}
@@ -0,0 +1,233 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    sanitize::{fit_name, sanitize},
+    Error, Post, Result,
+};
+
+/// Template of the path of downloaded files, relative to the folder of the
+/// session, e.g. `{artist}/{id} {title}.{ext}`.
+///
+/// Placeholders are replaced with the fields of the post:
+///
+/// - `{id}`, `{md5}`, `{rating}`, `{score}` and `{ext}`
+/// - `{artist}`, `{character}` and `{copyright}`, the tags of that type
+/// - `{date}`, the day the post was created, as `YYYY-MM-DD`
+/// - `{tags}`, all tags separated by spaces
+/// - `{title}`, the title of the post page, or its tags if the session is
+///   set to be `quick`
+///
+/// Values never contain path separators, so only `/` written in the
+/// template creates subfolders. Unknown values are replaced with `unknown`.
+/// Each file and folder name is then made valid on any platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Md5,
+    Artist,
+    Character,
+    Copyright,
+    Rating,
+    Score,
+    Date,
+    Tags,
+    Title,
+    Ext,
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "id" => Self::Id,
+            "md5" => Self::Md5,
+            "artist" => Self::Artist,
+            "character" => Self::Character,
+            "copyright" => Self::Copyright,
+            "rating" => Self::Rating,
+            "score" => Self::Score,
+            "date" => Self::Date,
+            "tags" => Self::Tags,
+            "title" => Self::Title,
+            "ext" => Self::Ext,
+            _ => return Err(Error::parse(format!("unknown placeholder {{{s}}}"), s)),
+        })
+    }
+}
+
+impl Default for Template {
+    /// `{id} {title}.{ext}`, the names of earlier versions.
+    fn default() -> Self {
+        "{id} {title}.{ext}".parse().unwrap()
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::parse("unclosed placeholder", s))?;
+            parts.push(Part::Field(rest[start + 1..start + end].parse()?));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+
+        if !parts.contains(&Part::Field(Field::Id)) && !parts.contains(&Part::Field(Field::Md5)) {
+            return Err(Error::parse(
+                "template needs {id} or {md5} to tell posts apart",
+                s,
+            ));
+        }
+        if parts
+            .iter()
+            .any(|p| matches!(p, Part::Text(t) if t.contains('}')))
+        {
+            return Err(Error::parse("unopened placeholder", s));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Text(t) => f.write_str(t)?,
+                Part::Field(field) => write!(f, "{{{}}}", format!("{field:?}").to_lowercase())?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Template {
+    /// Whether rendering the template needs the title of the post page.
+    pub(crate) fn uses_title(&self) -> bool {
+        self.parts.contains(&Part::Field(Field::Title))
+    }
+
+    /// Whether rendering the template needs the tags of the post grouped by
+    /// their type, e.g. `{artist}`.
+    pub(crate) fn uses_tag_types(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part,
+                Part::Field(Field::Artist | Field::Character | Field::Copyright)
+            )
+        })
+    }
+
+    /// Render the path of the file of given post, `title` being the title of
+    /// its page, if known.
+    pub(crate) fn render(&self, post: &Post, title: Option<&str>) -> String {
+        let mut path = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(t) => path.push_str(t),
+                Part::Field(field) => {
+                    let value = Self::value(*field, post, title).unwrap_or_default();
+                    let value = sanitize(&value);
+                    path.push_str(if value.is_empty() { "unknown" } else { &value });
+                }
+            }
+        }
+
+        path.split('/')
+            .map(|name| match name {
+                "" | "." | ".." => name.to_owned(),
+                _ => fit_name(name),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn value(field: Field, post: &Post, title: Option<&str>) -> Option<String> {
+        Some(match field {
+            Field::Id => post.id.to_string(),
+            Field::Md5 => post.md5.clone()?,
+            Field::Artist => post.tags_of("artist").join(" "),
+            Field::Character => post.tags_of("character").join(" "),
+            Field::Copyright => post.tags_of("copyright").join(" "),
+            Field::Rating => post.rating?.to_string(),
+            Field::Score => post.score?.to_string(),
+            Field::Date => date(post.created_at.as_deref()?)?,
+            Field::Tags => post.tags.join(" "),
+            Field::Title => match title {
+                Some(title) => title.to_owned(),
+                None => post.tags.join(" "),
+            },
+            Field::Ext => post.extension()?.to_owned(),
+        })
+    }
+}
+
+/// Get the day of a creation time, as `YYYY-MM-DD`.
+///
+/// Sites give it as `2023-01-21T01:55:39.000-05:00` (Danbooru), as
+/// `Sat Jan 21 01:55:39 -0600 2023` (Gelbooru API), as `2023-01-21 01:55:39`
+/// (Gelbooru pages) or as a Unix timestamp (Moebooru).
+fn date(created_at: &str) -> Option<String> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let s = created_at.trim();
+    let bytes = s.as_bytes();
+    if bytes.len() >= 10 && bytes[4] == b'-' && bytes[7] == b'-' {
+        return s.get(..10).map(str::to_owned);
+    }
+    if let Ok(timestamp) = s.parse::<u64>() {
+        let (year, month, day) = civil_from_days(timestamp / 86400);
+        return Some(format!("{year:04}-{month:02}-{day:02}"));
+    }
+
+    let fields: Vec<_> = s.split_whitespace().collect();
+    if let [_, month, day, _, _, year] = fields[..] {
+        let month = MONTHS.iter().position(|m| *m == month)? + 1;
+        let day: u32 = day.parse().ok()?;
+        let year: u32 = year.parse().ok()?;
+        return Some(format!("{year:04}-{month:02}-{day:02}"));
+    }
+
+    None
+}
+
+/// Convert days since 1970-01-01 into a date of the Gregorian calendar.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{self, Path, PathBuf},
+};
+
+use futures_util::{stream, StreamExt};
+
+use crate::{backend::md5_from_url, part::PART_EXTENSION, Error, Result, Session};
+
+/// Extensions of the sidecar files written next to downloads.
+const SIDECAR_EXTENSIONS: [&str; 2] = ["json", "txt"];
+
+/// A saved file, along with the id of its post.
+#[derive(Debug)]
+struct Saved {
+    path: PathBuf,
+    id: String,
+}
+
+impl Saved {
+    /// Find the post of the file at `path`: the one `recorded` as saved
+    /// there by the archive, or the one its name starts with, as in
+    /// `id title.ext`.
+    fn resolve(path: PathBuf, recorded: &HashMap<PathBuf, String>) -> Option<Self> {
+        if let Some(id) = path::absolute(&path).ok().and_then(|p| recorded.get(&p)) {
+            return Some(Self {
+                id: id.clone(),
+                path,
+            });
+        }
+
+        let name = path.file_name()?.to_str()?;
+        let (stem, _) = name.rsplit_once('.')?;
+        let id = stem.split(' ').next().unwrap_or_default();
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(Self {
+            id: id.to_owned(),
+            path,
+        })
+    }
+}
+
+/// Files found in a folder.
+#[derive(Debug, Default)]
+struct Found {
+    saved: Vec<Saved>,
+    /// Files of no known post.
+    unrecognized: usize,
+    unfinished: usize,
+}
+
+/// Outcome of checking a file.
+#[derive(Debug)]
+enum Check {
+    Valid,
+    /// The file does not match its post, for given reason.
+    Broken(String),
+    /// The file was broken and has been downloaded again.
+    Repaired,
+    /// Neither the archive nor the site tells the md5 of the post.
+    Unknown,
+}
+
+impl Session {
+    /// Check the files in the folder of the session, and in its subfolders,
+    /// against the md5 of their posts, as recorded in the archive or given
+    /// by the site.
+    ///
+    /// Files are matched to their post by the archive, or by their name
+    /// starting with the id of the post. Other files are listed and counted
+    /// as unrecognized.
+    ///
+    /// Broken files are listed, and downloaded again if the session is set
+    /// to `repair` them.
+    pub(crate) async fn verify(&self) -> Result<()> {
+        let folder = self.options.folder.as_deref().unwrap_or(".");
+        println!("Start verifying {folder}...");
+
+        let recorded: HashMap<_, _> = self
+            .archive
+            .saved_in(Path::new(folder))?
+            .into_iter()
+            .map(|(id, path)| (path, id))
+            .collect();
+        let mut found = Found::default();
+        Self::walk(Path::new(folder), &recorded, &mut found)?;
+
+        let results: Vec<_> = stream::iter(&found.saved)
+            .map(|file| async move { (file, self.check(file).await) })
+            .buffer_unordered(self.concurrency())
+            .collect()
+            .await;
+
+        let (mut valid, mut broken, mut repaired, mut unknown, mut err_count) = (0, 0, 0, 0, 0);
+        for (file, result) in results {
+            let path = file.path.display();
+            match result {
+                Ok(Check::Valid) => valid += 1,
+                Ok(Check::Broken(reason)) => {
+                    println!("{path}: {reason}");
+                    broken += 1;
+                }
+                Ok(Check::Repaired) => repaired += 1,
+                Ok(Check::Unknown) => {
+                    println!("{path}: md5 unknown, skipping.");
+                    unknown += 1;
+                }
+                Err(e) => {
+                    eprintln!("failed verifying {path}: {e}");
+                    err_count += 1;
+                }
+            }
+        }
+
+        println!(
+            "Finished verifying {} files. Valid: {valid}, broken: {broken}, repaired: {repaired}, unknown: {unknown}, unrecognized: {}, unfinished: {}, error count: {err_count}",
+            found.saved.len(),
+            found.unrecognized,
+            found.unfinished,
+        );
+
+        Ok(())
+    }
+
+    /// Collect the saved posts in `dir` and its subfolders, and count the
+    /// unrecognized files and unfinished downloads, leaving hidden and
+    /// sidecar files out.
+    fn walk(dir: &Path, recorded: &HashMap<PathBuf, String>, found: &mut Found) -> Result<()> {
+        for entry in fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+            let entry = entry.map_err(|e| Error::io(dir, e))?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+
+            if entry.file_type().map_err(|e| Error::io(&path, e))?.is_dir() {
+                Self::walk(&path, recorded, found)?;
+            } else if name.rsplit('.').take(2).any(|ext| ext == PART_EXTENSION) {
+                if name.ends_with(PART_EXTENSION) {
+                    println!("{}: unfinished download", path.display());
+                    found.unfinished += 1;
+                }
+            } else if name
+                .rsplit_once('.')
+                .is_some_and(|(_, ext)| SIDECAR_EXTENSIONS.contains(&ext))
+            {
+                continue;
+            } else if let Some(saved) = Saved::resolve(path.clone(), recorded) {
+                found.saved.push(saved);
+            } else {
+                println!("{}: no post found, skipping.", path.display());
+                found.unrecognized += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check given file, downloading it again if broken and the session is
+    /// set to repair it.
+    async fn check(&self, file: &Saved) -> Result<Check> {
+        let mut post = None;
+        let expected = match self.archive.md5(&file.id)? {
+            Some(md5) => md5,
+            None => {
+                let p = self.get_post(&file.id).await?;
+                let md5 = p
+                    .md5
+                    .as_deref()
+                    .or_else(|| p.file_url.as_deref().and_then(md5_from_url))
+                    .map(str::to_owned);
+                post = Some(p);
+                match md5 {
+                    Some(md5) => md5,
+                    None => return Ok(Check::Unknown),
+                }
+            }
+        };
+
+        let size = fs::metadata(&file.path)
+            .map_err(|e| Error::io(&file.path, e))?
+            .len();
+        let reason = match post.as_ref().and_then(|p| p.file_size) {
+            Some(expected) if expected != size => {
+                Some(format!("size is {size} instead of {expected}"))
+            }
+            _ => {
+                let actual = Self::md5_of(&file.path)?;
+                (!actual.eq_ignore_ascii_case(&expected))
+                    .then(|| format!("md5 is {actual} instead of {expected}"))
+            }
+        };
+        let Some(reason) = reason else {
+            return Ok(Check::Valid);
+        };
+        if !self.options.repair {
+            return Ok(Check::Broken(reason));
+        }
+
+        println!("{}: {reason}, downloading again...", file.path.display());
+        let post = match post {
+            Some(post) => post,
+            None => self.get_post(&file.id).await?,
+        };
+        let path = file.path.to_str().ok_or_else(|| {
+            Error::parse("path is not valid UTF-8", &file.path.display().to_string())
+        })?;
+        self.save_post_at(&post, path).await?;
+
+        Ok(Check::Repaired)
+    }
+
+    fn md5_of(path: &Path) -> Result<String> {
+        let mut context = md5::Context::new();
+        File::open(path)
+            .and_then(|mut f| io::copy(&mut f, &mut context))
+            .map_err(|e| Error::io(path, e))?;
+
+        Ok(format!("{:x}", context.compute()))
+    }
+}
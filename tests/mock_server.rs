@@ -0,0 +1,538 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use getbooru::{RetryPolicy, Session};
+
+/// A request received by the server, with lowercase header names.
+#[derive(Debug, Clone)]
+struct Request {
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// A response of the server, `200 OK` unless told otherwise.
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+    /// Number of bytes of the body sent before closing the connection, if
+    /// not all of them.
+    cut: Option<usize>,
+}
+
+impl Response {
+    fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: "200 OK",
+            headers: Vec::new(),
+            body: body.into(),
+            cut: None,
+        }
+    }
+
+    fn not_found() -> Self {
+        Self {
+            status: "404 Not Found",
+            ..Self::ok("")
+        }
+    }
+
+    fn status(self, status: &'static str) -> Self {
+        Self { status, ..self }
+    }
+
+    fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    fn cut(self, len: usize) -> Self {
+        Self {
+            cut: Some(len),
+            ..self
+        }
+    }
+}
+
+/// Bind a server to a local port, returning it along with its host.
+fn bind() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+
+    (listener, host)
+}
+
+/// Answer the requests to `listener` with `route` from a thread, and record
+/// them.
+fn serve(
+    listener: TcpListener,
+    route: impl Fn(&Request) -> Response + Send + 'static,
+) -> Arc<Mutex<Vec<Request>>> {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+
+    let seen = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let path = line
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_owned();
+            // Requests have no body.
+            let mut headers = HashMap::new();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+                }
+            }
+
+            let request = Request { path, headers };
+            let res = route(&request);
+            seen.lock().unwrap().push(request);
+            let mut head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                res.status,
+                res.body.len()
+            );
+            for (name, value) in &res.headers {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+            head.push_str("\r\n");
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&res.body[..res.cut.unwrap_or(res.body.len())]);
+        }
+    });
+
+    requests
+}
+
+/// Paths of the requests received.
+fn paths(requests: &Mutex<Vec<Request>>) -> Vec<String> {
+    let requests = requests.lock().unwrap();
+    requests.iter().map(|r| r.path.clone()).collect()
+}
+
+/// A new download folder for given test.
+fn temp_folder(test: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("getbooru-mock-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    folder
+}
+
+/// Pages of a site listing post 12 only, showing its file with `image`.
+fn scraped_page(path: &str, image: &str) -> Option<String> {
+    let page = if path.contains("s=list") && path.contains("pid=0") {
+        r#"<article class="thumbnail-preview">
+            <a href="index.php?page=post&amp;s=view&amp;id=12"><img></a>
+        </article>"#
+            .to_owned()
+    } else if path.contains("s=list") {
+        String::new()
+    } else if path.contains("s=view&id=12") {
+        format!(
+            r#"<title>Mock &amp; post</title>
+            <ul id="tag-sidebar">
+                <li class="tag-type-general"><a href="index.php?page=post&amp;s=list&amp;tags=1girl">1girl</a></li>
+            </ul>
+            <div id="stats"><ul><li>Id: 12</li><li>Rating: General</li></ul></div>
+            {image}"#
+        )
+    } else {
+        return None;
+    };
+
+    Some(format!("<html><body>{page}</body></html>"))
+}
+
+#[tokio::test]
+async fn scrape_and_download_from_cdn() {
+    let file = b"not really a png".to_vec();
+    let md5 = format!("{:x}", md5::compute(&file));
+
+    let (cdn, cdn_host) = bind();
+    let cdn_requests = {
+        let (file, name) = (file.clone(), format!("/images/ab/cd/{md5}.png"));
+        serve(cdn, move |req| match req.path == name {
+            true => Response::ok(file.clone()),
+            false => Response::not_found(),
+        })
+    };
+    let (site, site_host) = bind();
+    let site_requests = {
+        let image = format!(r#"<img id="image" src="http://{cdn_host}/images/ab/cd/{md5}.png">"#);
+        serve(site, move |req| match scraped_page(&req.path, &image) {
+            Some(page) => Response::ok(page),
+            None => Response::not_found(),
+        })
+    };
+
+    let folder = temp_folder("cdn");
+    let mut options = Session::options();
+    options
+        .base_url(&format!("http://{site_host}"))
+        .cdn_host(&cdn_host)
+        .tags("1girl")
+        .folder(folder.to_str().unwrap())
+        .max_attempts(1);
+    options.create().start().await.unwrap();
+
+    let saved = fs::read(folder.join("12 Mock & post.png"));
+    fs::remove_dir_all(&folder).unwrap();
+    assert_eq!(saved.unwrap(), file);
+
+    let site_paths = paths(&site_requests);
+    assert!(site_paths.iter().any(|p| p.contains("s=list")));
+    assert!(site_paths.iter().any(|p| p.contains("s=view&id=12")));
+    assert_eq!(paths(&cdn_requests), [format!("/images/ab/cd/{md5}.png")]);
+}
+
+#[tokio::test]
+async fn scrape_and_download_from_own_host() {
+    let file = b"not really a gif".to_vec();
+    let md5 = format!("{:x}", md5::compute(&file));
+
+    let (site, host) = bind();
+    let site_requests = {
+        let (file, name) = (file.clone(), format!("/images/ab/cd/{md5}.gif"));
+        // Another dotted attribute follows the url on the same line.
+        let image = format!(r#"<img id="image" src="http://{host}{name}" alt="x.y">"#);
+        serve(site, move |req| {
+            if req.path == name {
+                return Response::ok(file.clone());
+            }
+            match scraped_page(&req.path, &image) {
+                Some(page) => Response::ok(page),
+                None => Response::not_found(),
+            }
+        })
+    };
+
+    let folder = temp_folder("own-host");
+    let mut options = Session::options();
+    options
+        .base_url(&format!("http://{host}"))
+        .tags("1girl")
+        .folder(folder.to_str().unwrap())
+        .max_attempts(1);
+    options.create().start().await.unwrap();
+
+    let saved = fs::read(folder.join("12 Mock & post.gif"));
+    fs::remove_dir_all(&folder).unwrap();
+    assert_eq!(saved.unwrap(), file);
+    assert!(paths(&site_requests).contains(&format!("/images/ab/cd/{md5}.gif")));
+}
+
+#[tokio::test]
+async fn artist_folder_from_page_of_api_post() {
+    let file = b"not really a jpeg".to_vec();
+    let md5 = format!("{:x}", md5::compute(&file));
+
+    let (site, host) = bind();
+    let site_requests = {
+        let (file, name) = (file.clone(), format!("/images/ab/cd/{md5}.jpg"));
+        let file_url = format!("http://{host}{name}");
+        serve(site, move |req| {
+            if req.path == name {
+                return Response::ok(file.clone());
+            }
+            let body = if req.path.contains("s=post&q=index") && req.path.contains("pid=0") {
+                format!(
+                    r#"{{"@attributes": {{"limit": 100, "offset": 0, "count": 1}},
+                    "post": [{{"id": 34, "md5": "{md5}", "rating": "general",
+                    "tags": "1girl artist_name", "file_url": "{file_url}"}}]}}"#
+                )
+            } else if req.path.contains("s=view&id=34") {
+                format!(
+                    r#"<html><body><ul id="tag-sidebar">
+                        <li class="tag-type-artist"><a href="index.php?page=post&amp;s=list&amp;tags=artist_name">artist name</a></li>
+                        <li class="tag-type-general"><a href="index.php?page=post&amp;s=list&amp;tags=1girl">1girl</a></li>
+                    </ul><img id="image" src="{file_url}"></body></html>"#
+                )
+            } else {
+                return Response::not_found();
+            };
+            Response::ok(body)
+        })
+    };
+
+    let folder = temp_folder("artist");
+    let mut options = Session::options();
+    options
+        .base_url(&format!("http://{host}"))
+        .tags("1girl")
+        .api(true)
+        .template("{artist}/{id}.{ext}".parse().unwrap())
+        .folder(folder.to_str().unwrap())
+        .max_attempts(1);
+    options.create().start().await.unwrap();
+
+    let saved = fs::read(folder.join("artist_name").join("34.jpg"));
+    fs::remove_dir_all(&folder).unwrap();
+    assert_eq!(saved.unwrap(), file);
+    assert!(paths(&site_requests)
+        .iter()
+        .any(|p| p.contains("s=view&id=34")));
+}
+
+/// Answer with `file`, tagged `etag`, or with the part of it asked for.
+///
+/// Servers honoring `If-Range` send the whole file if it does not match.
+fn ranged(req: &Request, file: &[u8], etag: &str, honors_if_range: bool) -> Response {
+    let start = req
+        .header("range")
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.strip_suffix('-'))
+        .and_then(|r| r.parse::<usize>().ok());
+    let matches = !honors_if_range || req.header("if-range").is_none_or(|v| v == etag);
+
+    let res = match start {
+        Some(start) if matches && start < file.len() => Response::ok(&file[start..])
+            .status("206 Partial Content")
+            .header(
+                "Content-Range",
+                format!("bytes {start}-{}/{}", file.len() - 1, file.len()),
+            ),
+        _ => Response::ok(file),
+    };
+    res.header("Accept-Ranges", "bytes").header("ETag", etag)
+}
+
+/// Download post 12 whose file is served as `etag`, after an earlier
+/// attempt left `part` of a file tagged `part_etag`.
+///
+/// Checks that the file is saved whole, and returns its requests.
+async fn resume(
+    test: &str,
+    part: &[u8],
+    part_etag: &str,
+    etag: &'static str,
+    honors_if_range: bool,
+) -> Vec<Request> {
+    let file = b"0123456789 not really a gif".to_vec();
+    let md5 = format!("{:x}", md5::compute(&file));
+
+    let (site, host) = bind();
+    let name = format!("/images/ab/cd/{md5}.gif");
+    let requests = {
+        let (file, name) = (file.clone(), name.clone());
+        let image = format!(r#"<img id="image" src="http://{host}{name}">"#);
+        serve(site, move |req| {
+            if req.path == name {
+                return ranged(req, &file, etag, honors_if_range);
+            }
+            match scraped_page(&req.path, &image) {
+                Some(page) => Response::ok(page),
+                None => Response::not_found(),
+            }
+        })
+    };
+
+    let folder = temp_folder(test);
+    fs::create_dir_all(&folder).unwrap();
+    let path = folder.join("12 Mock & post.gif");
+    fs::write(path.with_extension("gif.part"), part).unwrap();
+    let validator = format!(
+        r#"{{"size": {}, "etag": "{}", "last_modified": null}}"#,
+        file.len(),
+        part_etag.replace('"', "\\\""),
+    );
+    fs::write(path.with_extension("gif.part.json"), validator).unwrap();
+
+    let mut options = Session::options();
+    options
+        .base_url(&format!("http://{host}"))
+        .tags("1girl")
+        .folder(folder.to_str().unwrap())
+        .retry(RetryPolicy {
+            backoff: std::time::Duration::from_millis(10),
+            ..Default::default()
+        });
+    options.create().start().await.unwrap();
+
+    let saved = fs::read(&path).unwrap();
+    let leftovers = [
+        path.with_extension("gif.part").exists(),
+        path.with_extension("gif.part.json").exists(),
+    ];
+    fs::remove_dir_all(&folder).unwrap();
+    assert_eq!(saved, file);
+    assert_eq!(leftovers, [false, false]);
+
+    let requests = requests.lock().unwrap();
+    requests
+        .iter()
+        .filter(|r| r.path == name)
+        .cloned()
+        .collect()
+}
+
+#[tokio::test]
+async fn resume_partial_download() {
+    let requests = resume("resume", b"0123456789", r#""v1""#, r#""v1""#, true).await;
+
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("range"), Some("bytes=10-"));
+    assert_eq!(requests[0].header("if-range"), Some(r#""v1""#));
+}
+
+#[tokio::test]
+async fn start_over_when_file_changed() {
+    let requests = resume("changed", b"abcdefghij", r#""v0""#, r#""v1""#, true).await;
+
+    // The server sends the whole file, which replaces the part.
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("if-range"), Some(r#""v0""#));
+}
+
+#[tokio::test]
+async fn start_over_when_part_does_not_match() {
+    let requests = resume("mismatch", b"abcdefghij", r#""v0""#, r#""v1""#, false).await;
+
+    // The part sent does not belong to the same file, so it is discarded
+    // and the file asked for again in full.
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].header("range"), Some("bytes=10-"));
+    assert_eq!(requests[1].header("range"), None);
+}
+
+#[tokio::test]
+async fn resume_after_connection_closed() {
+    let file = b"0123456789 not really a gif".to_vec();
+    let md5 = format!("{:x}", md5::compute(&file));
+
+    let (site, host) = bind();
+    let name = format!("/images/ab/cd/{md5}.gif");
+    let requests = {
+        let (file, name) = (file.clone(), name.clone());
+        let image = format!(r#"<img id="image" src="http://{host}{name}">"#);
+        serve(site, move |req| {
+            if req.path != name {
+                return match scraped_page(&req.path, &image) {
+                    Some(page) => Response::ok(page),
+                    None => Response::not_found(),
+                };
+            }
+            // The first attempt ends after 10 bytes.
+            match req.header("range") {
+                Some(_) => ranged(req, &file, r#""v1""#, true),
+                None => ranged(req, &file, r#""v1""#, true).cut(10),
+            }
+        })
+    };
+
+    let folder = temp_folder("closed");
+    let mut options = Session::options();
+    options
+        .base_url(&format!("http://{host}"))
+        .tags("1girl")
+        .folder(folder.to_str().unwrap())
+        .retry(RetryPolicy {
+            backoff: std::time::Duration::from_millis(10),
+            ..Default::default()
+        });
+    options.create().start().await.unwrap();
+
+    let saved = fs::read(folder.join("12 Mock & post.gif"));
+    fs::remove_dir_all(&folder).unwrap();
+    assert_eq!(saved.unwrap(), file);
+
+    let requests = requests.lock().unwrap();
+    let ranges: Vec<_> = requests
+        .iter()
+        .filter(|r| r.path == name)
+        .map(|r| (r.header("range"), r.header("if-range")))
+        .collect();
+    assert_eq!(ranges, [(None, None), (Some("bytes=10-"), Some(r#""v1""#))]);
+}
+
+#[tokio::test]
+async fn scrape_same_page_when_api_limit_reached() {
+    let file = b"not really a png".to_vec();
+    let md5 = format!("{:x}", md5::compute(&file));
+
+    let (site, host) = bind();
+    let requests = {
+        let (file, name) = (file.clone(), format!("/images/ab/cd/{md5}.png"));
+        let image = format!(r#"<img id="image" src="http://{host}{name}">"#);
+        serve(site, move |req| {
+            if req.path == name {
+                Response::ok(file.clone())
+            } else if req.path.contains("s=post&q=index") {
+                Response::ok(
+                    r#"<?xml version="1.0" encoding="UTF-8"?>
+                    <response success="false" reason="Search limit exceeded for today."/>"#,
+                )
+            } else if req.path.contains("s=list") && req.path.contains("pid=42") {
+                // The second page lists post 12.
+                Response::ok(scraped_page("s=list&pid=0", "").unwrap())
+            } else {
+                match scraped_page(&req.path, &image) {
+                    Some(page) => Response::ok(page),
+                    None => Response::not_found(),
+                }
+            }
+        })
+    };
+
+    let folder = temp_folder("limit");
+    let mut options = Session::options();
+    options
+        .base_url(&format!("http://{host}"))
+        .tags("1girl")
+        .api(true)
+        .start(2)
+        .folder(folder.to_str().unwrap())
+        .max_attempts(1);
+    options.create().start().await.unwrap();
+
+    let saved = fs::read(folder.join("12 Mock & post.png"));
+    fs::remove_dir_all(&folder).unwrap();
+    assert_eq!(saved.unwrap(), file);
+
+    // The API is asked once, then page 2 and the following are scraped.
+    let listings: Vec<_> = paths(&requests)
+        .into_iter()
+        .filter(|p| p.contains("s=post&q=index") || p.contains("s=list"))
+        .map(|p| {
+            let api = p.contains("q=index");
+            let pid = p
+                .split("pid=")
+                .nth(1)
+                .unwrap()
+                .split('&')
+                .next()
+                .unwrap()
+                .to_owned();
+            (api, pid)
+        })
+        .collect();
+    assert_eq!(
+        listings,
+        [
+            (true, "1".to_owned()),
+            (false, "42".to_owned()),
+            (false, "84".to_owned()),
+        ]
+    );
+}